@@ -1,7 +1,7 @@
 use miette::IntoDiagnostic;
 use mimalloc::MiMalloc;
 use std::{env, fs::read, time::Instant};
-use structural_types::error::MultiFile;
+use structural_types::{error::MultiFile, refinement::eval::AssertMode};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -13,14 +13,14 @@ fn main() -> miette::Result<()> {
     };
     let input = read(input).into_diagnostic()?;
     println!("last byte is {}", input.last().unwrap());
-    let args = vec![input.len() as i32];
+    let args = vec![input.len() as i64];
 
-    let source = MultiFile::new(file);
-    structural_types::desugar::check(&source);
+    let source = MultiFile::new(file)?;
+    structural_types::desugar::check(&source)?;
     println!("typechecking succeeded!");
 
     let instant = Instant::now();
-    let result = structural_types::desugar::run(source, func, args, input);
+    let result = structural_types::desugar::run(source, func, args, input, AssertMode::Ghost)?;
     println!("the result is {result:?}");
     println!("took: {:.2} seconds", instant.elapsed().as_secs_f32());
     Ok(())
@@ -1,20 +1,48 @@
+use miette::IntoDiagnostic;
 use mimalloc::MiMalloc;
-use std::env;
-use structural_types::error::MultiFile;
+use std::{env, fs};
+use structural_types::{error::MultiFile, refinement::eval::AssertMode};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 fn main() -> miette::Result<()> {
     let args: Vec<_> = env::args().collect();
-    let [_, file, func, args @ ..] = &*args else {
-        panic!("not enough arguments")
-    };
-    let args = args.iter().map(|x| x.parse().unwrap()).collect();
+    match &*args {
+        [_, mode, file, out] if mode == "emit-wasm" => {
+            let source = MultiFile::new(file)?;
+            structural_types::desugar::check(&source)?;
+            let module = structural_types::desugar::compile(source)?;
+            fs::write(out, module.encode()).into_diagnostic()?;
+        }
+        [_, mode, file] if mode == "emit-wat" => {
+            let source = MultiFile::new(file)?;
+            structural_types::desugar::check(&source)?;
+            let module = structural_types::desugar::compile(source)?;
+            print!("{module}");
+        }
+        [_, mode, file] if mode == "fmt" => {
+            let source = MultiFile::new(file)?;
+            print!("{}", source.format()?);
+        }
+        [_, mode, file, func, args @ ..] if mode == "run-unchecked" => {
+            let args = args.iter().map(|x| x.parse().unwrap()).collect();
 
-    let source = MultiFile::new(file);
-    structural_types::desugar::check(&source);
-    let result = structural_types::desugar::run(source, func, args, vec![]);
-    println!("the result is {result:?}");
+            let source = MultiFile::new(file)?;
+            let result =
+                structural_types::desugar::run(source, func, args, vec![], AssertMode::Checked)?;
+            println!("the result is {result:?}");
+        }
+        [_, file, func, args @ ..] => {
+            let args = args.iter().map(|x| x.parse().unwrap()).collect();
+
+            let source = MultiFile::new(file)?;
+            structural_types::desugar::check(&source)?;
+            let result =
+                structural_types::desugar::run(source, func, args, vec![], AssertMode::Ghost)?;
+            println!("the result is {result:?}");
+        }
+        _ => panic!("not enough arguments"),
+    }
     Ok(())
 }
@@ -1,32 +1,37 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     marker::PhantomData,
     rc::{Rc, Weak},
 };
 
-use miette::{Diagnostic, SourceSpan};
+use miette::{Diagnostic, Report, SourceSpan};
 use thiserror::Error;
 
 use self::{
-    types::{NameList, Named},
-    value::IntoScope,
+    types::NameList,
+    value::{Effect, IntoScope},
+};
+use crate::refinement::{
+    self,
+    eval::{AssertMode, EvalErr, Int},
+    typing::zip_eq,
+    Lambda, Val,
 };
-use crate::refinement::{self, Lambda, Val};
 use crate::uninit_rc::UninitRc;
 use crate::{
     error::MultiFile,
     parse::{
-        expr::{Block, Def, FuncDef, If, Let, Module, Spanned, Stmt, Value},
+        expr::{Bind, Block, Def, FuncDef, If, Let, Match, Module, Pattern, Spanned, Stmt, Value},
         lexer::Lexer,
     },
 };
 use crate::{
-    parse::types::{NamedConstraint, NegTyp},
+    parse::types::{Constraint, NamedConstraint, NegTyp, Param, ParamTyp},
     refinement::Expr,
 };
 use crate::{
     parse::{code::NegTypParser, types::PosTyp},
-    Nested,
+    wasm, Nested,
 };
 
 mod types;
@@ -36,7 +41,11 @@ mod value;
 pub struct Desugar<T: Val> {
     pub types: types::DesugarTypes,
     pub vars: HashMap<String, Nested<T>>,
-    labels: HashMap<String, (NegTyp, T::Func)>,
+    pub(crate) labels: HashMap<String, (NegTyp, T::Func)>,
+    // variables that point to functions, with their contracts
+    contracts: HashMap<String, NegTyp>,
+    // top-level functions that can be pointed to, with their index in the table
+    table: Rc<HashMap<String, (usize, NegTyp)>>,
     ret: Rc<Spanned<PosTyp>>,
 }
 
@@ -47,106 +56,335 @@ pub struct WeakFuncDef<T: Val> {
 }
 
 impl<T: Val> Desugar<T> {
-    pub fn convert_value(&self, value: &Spanned<Vec<Value>>) -> refinement::Value<T> {
+    /// Literals in the value take the sizes of the parameters they are passed to.
+    pub fn convert_value(
+        &self,
+        value: &Spanned<Vec<Value>>,
+        sizes: &[u32],
+    ) -> Result<refinement::Value<T>, ConvertErr> {
         let value_iter = value.val.iter();
-        let inj = self
-            .types
-            .source
-            .unwrap(value_iter.map(|val| val.convert(&self.vars)).collect());
-        refinement::Value {
+        let inj: Vec<Vec<_>> = value_iter
+            .map(|val| val.convert_all(&self.vars))
+            .collect::<Result<_, _>>()?;
+        let inj: Vec<_> = inj.into_iter().flatten().collect();
+        if inj.len() != sizes.len() {
+            let (have, need) = (inj.len(), sizes.len());
+            let span = Some(value.span);
+            return Err(ArgsErr { span, have, need }.into());
+        }
+        let fitted = inj.into_iter().zip(sizes);
+        let span = Some(value.span);
+        let inj = fitted.map(|(free, size)| free.fit(*size, span));
+        let inj = inj.collect::<Result<_, _>>()?;
+        Ok(refinement::Value {
             span: Some(value.span),
             inj,
             scope: Some(self.vars.clone()),
+        })
+    }
+
+    /// Find a function by name, together with the sizes of its arguments
+    /// and the types of its return values.
+    #[allow(clippy::type_complexity)]
+    fn func(
+        &self,
+        name: &Spanned<String>,
+    ) -> Result<(refinement::Thunk<T>, Vec<u32>, Vec<ParamTyp>), ConvertErr> {
+        use refinement::builtin::Builtin;
+        if name.val.starts_with('@') {
+            let (builtin, args, rets) = match name.val.as_str() {
+                "@read8" => (Builtin::Read8, 1, 1),
+                "@read32" => (Builtin::Read32, 1, 1),
+                "@write8" => (Builtin::Write8, 2, 0),
+                "@write32" => (Builtin::Write32, 2, 0),
+                "@alloc" => (Builtin::Alloc(self.types.data.len() as u32), 1, 1),
+                _ => return Err(ScopeErr { span: name.span }.into()),
+            };
+            return Ok((
+                refinement::Thunk::Builtin(builtin),
+                vec![32; args],
+                vec![ParamTyp::Int(32); rets],
+            ));
+        }
+        let (typ, func) = match (self.contracts.get(&name.val), self.vars.get(&name.val)) {
+            (Some(typ), Some(_)) => {
+                let ptr = Value::Var(name.clone(), vec![]);
+                let func = refinement::Thunk::Indirect {
+                    ptr: ptr.convert(&self.vars)?,
+                    args: self.types.sizes(&typ.args.val.params)?,
+                    rets: self.types.sizes(&typ.ret.val.params)?,
+                };
+                (typ, func)
+            }
+            _ => {
+                let (typ, local) = self.labels.try_get(name)?;
+                (typ, refinement::Thunk::Local(local.clone()))
+            }
+        };
+        let args = self.types.sizes(&typ.args.val.params)?;
+        let rets = typ.ret.val.params.iter().map(|ret| ret.typ.clone());
+        Ok((func, args, rets.collect()))
+    }
+
+    /// New variables shadow earlier variables together with their contracts.
+    fn bind(&mut self, vars: Vec<(String, Nested<T>)>) {
+        for (name, _) in &vars {
+            self.contracts.remove(name);
         }
+        self.vars.extend(vars);
+    }
+
+    /// Variables in the type that point to functions can be called.
+    fn contracts(typ: &PosTyp) -> Vec<(String, NegTyp)> {
+        let contracts = typ.parts.iter().filter_map(|part| match &part.val {
+            Constraint::Func(name, contract) => Some((name.val.clone(), contract.clone())),
+            _ => None,
+        });
+        contracts.collect()
+    }
+
+    /// Array reads and calls in the values of a statement are done before the statement,
+    /// from left to right and the arguments of a call before the call.
+    fn convert_effect(
+        &self,
+        effect: &Effect,
+        block: &Rc<Spanned<Block>>,
+    ) -> Result<Expr<T>, ConvertErr> {
+        let (func, arg, typ) = match effect {
+            Effect::Read(read) => {
+                let addr = read.addr(&self.vars)?;
+                let arg = refinement::Value {
+                    span: Some(effect.span()),
+                    inj: vec![addr],
+                    scope: Some(self.vars.clone()),
+                };
+                let read8 = refinement::builtin::Builtin::Read8;
+                (refinement::Thunk::Builtin(read8), arg, ParamTyp::Int(32))
+            }
+            Effect::Call(bind) => {
+                let (func, args, rets) = self.func(value::call_name(bind))?;
+                let typ = match &*rets {
+                    [ret] => ret.clone(),
+                    _ => {
+                        let span = effect.span();
+                        return Err(CallErr::NumRets { span }.into());
+                    }
+                };
+                (func, self.convert_value(&bind.args, &args)?, typ)
+            }
+            Effect::FuncPtr(name) => {
+                let (idx, typ) = self.table.try_get(name)?;
+                let typ = self.types.convert_neg(typ.clone())?;
+                let ptr = refinement::builtin::Builtin::FuncPtr(*idx, typ);
+                let arg = refinement::Value {
+                    span: Some(effect.span()),
+                    inj: vec![],
+                    scope: Some(self.vars.clone()),
+                };
+                (refinement::Thunk::Builtin(ptr), arg, ParamTyp::Int(32))
+            }
+        };
+
+        let param = Param {
+            name: effect.key(),
+            typ,
+        };
+        let (this, block) = (self.clone(), block.clone());
+        let rest = Rc::new(refinement::Lambda {
+            _val: PhantomData,
+            func: move |args: &[T]| {
+                let mut this = this.clone();
+                let vars = this
+                    .types
+                    .consume_args(args, std::slice::from_ref(&param))?;
+                this.bind(vars);
+                this.convert_expr(&block)
+            },
+        });
+        Ok(refinement::Expr::App(func, arg, rest))
     }
 
-    pub fn convert_expr(mut self, block: &Spanned<Block>) -> refinement::Spanned<Expr<T>> {
+    pub fn convert_expr(
+        mut self,
+        block: &Rc<Spanned<Block>>,
+    ) -> Result<refinement::Spanned<Expr<T>>, ConvertErr> {
         let span = block.span;
 
+        let values = block.val.values();
+        values.iter().try_for_each(|val| val.check_reads())?;
+        let mut effects = values.into_iter().flat_map(Value::effects);
+        if let Some(effect) = effects.find(|effect| !self.vars.contains_key(&effect.key())) {
+            let expr = self.convert_effect(&effect, block)?;
+            return Ok(refinement::Spanned { span, val: expr });
+        }
+
         let expr = match &block.val {
             Block::End(bind) => match bind.func.as_ref() {
+                // indirect calls return through a continuation
+                Some(func) if !self.labels.contains_key(&func.val) => {
+                    let (func, args, _rets) = self.func(func)?;
+                    let value = self.convert_value(&bind.args, &args)?;
+                    let scope = value.scope.clone();
+                    let rest = Rc::new(refinement::Lambda {
+                        _val: PhantomData,
+                        func: move |args: &[T]| {
+                            Ok(refinement::Spanned {
+                                span,
+                                val: refinement::Expr::Return(refinement::Value {
+                                    inj: args.iter().cloned().map(refinement::Free::Var).collect(),
+                                    span: Some(span),
+                                    scope: scope.clone(),
+                                }),
+                            })
+                        },
+                    });
+                    refinement::Expr::App(func, value, rest)
+                }
                 Some(func) => {
-                    let (_typ, label) = self.types.source.unwrap(self.labels.try_get(func));
-                    let value = self.convert_value(&bind.args);
+                    let (typ, label) = self.labels.try_get(func)?;
+                    let args = self.types.sizes(&typ.args.val.params)?;
+                    let value = self.convert_value(&bind.args, &args)?;
                     refinement::Expr::Loop(label.clone(), value)
                 }
                 None => {
-                    let value = self.convert_value(&bind.args);
+                    let rets = self.types.sizes(&self.ret.val.params)?;
+                    let value = self.convert_value(&bind.args, &rets)?;
                     refinement::Expr::Return(value)
                 }
             },
+            Block::IfElse(If { val, block: then }, other) => {
+                let local = val.convert(&self.vars)?;
+                let other = self.clone().convert_expr(other)?;
+                let then = self.convert_expr(then)?;
+                refinement::Expr::Match(local, vec![other, then])
+            }
+            Block::Match(m @ Match { val, arms }) => {
+                let local = val.convert(&self.vars)?;
+                // the grammar does not allow a match without arms
+                let (last, rest) = arms.split_last().unwrap();
+                if last.pattern.val != Pattern::Any {
+                    let span = last.pattern.span;
+                    return Err(PatternErr { span }.into());
+                }
+                let mut dense = rest.iter().enumerate();
+                if dense.all(|(i, arm)| arm.pattern.val == Pattern::Int(i as u64)) {
+                    let branches = arms.iter();
+                    let branches = branches.map(|arm| self.clone().convert_expr(&arm.block));
+                    refinement::Expr::Match(local, branches.collect::<Result<_, _>>()?)
+                } else {
+                    // other patterns are compared one by one, like an `if` for every arm
+                    let mut other = self.clone().convert_expr(&last.block)?;
+                    for arm in rest.iter().rev() {
+                        let Pattern::Int(lit) = arm.pattern.val else {
+                            let span = arm.pattern.span;
+                            return Err(PatternErr { span }.into());
+                        };
+                        let cond = m.cond(&local, lit, arm.pattern.span)?;
+                        let then = self.clone().convert_expr(&arm.block)?;
+                        let val = refinement::Expr::Match(cond, vec![other, then]);
+                        other = refinement::Spanned { span, val };
+                    }
+                    other.val
+                }
+            }
             Block::Stmt { step, next } => match &step.val {
                 Stmt::Debug => {
-                    let rest = self.convert_expr(next);
+                    let rest = self.convert_expr(next)?;
                     refinement::Expr::Debug(Box::new(rest))
                 }
-                Stmt::Let(Let { names, bind }) => {
-                    let func_name = bind.func.as_ref().unwrap();
-
-                    let func = if func_name.val.starts_with('@') {
-                        let builtin = match func_name.val.as_str() {
-                            "@read8" => refinement::builtin::Builtin::Read8,
-                            "@read32" => refinement::builtin::Builtin::Read32,
-                            "@write8" => refinement::builtin::Builtin::Write8,
-                            "@write32" => refinement::builtin::Builtin::Write32,
-                            "@alloc" => refinement::builtin::Builtin::Alloc,
-                            _ => self.types.source.unwrap(Err(ScopeErr {
-                                span: func_name.span,
-                            })),
-                        };
-                        refinement::Thunk::Builtin(builtin)
-                    } else {
-                        let (_typ, local) =
-                            self.types.source.unwrap(self.labels.try_get(func_name));
-                        refinement::Thunk::Local(local.clone())
+                Stmt::Assert(val) => {
+                    let prop = refinement::Value {
+                        span: Some(step.span),
+                        inj: vec![val.convert(&self.vars)?],
+                        scope: Some(self.vars.clone()),
                     };
-
-                    let arg = self.convert_value(&bind.args);
-                    let rest = self.convert_lambda(next, None, names);
-                    refinement::Expr::App(func, arg, rest)
+                    let rest = self.convert_expr(next)?;
+                    refinement::Expr::Assert(prop, Box::new(rest))
                 }
+                Stmt::Let(Let { names, bind }) => self.convert_call(names, bind, next)?,
+                Stmt::Call(bind) => self.convert_call(&[], bind, next)?,
                 Stmt::FuncDef(FuncDef {
                     name,
                     typ,
                     block: def,
                 }) => {
-                    let arg_names = &typ.args.val.names;
+                    let arg_params = &typ.args.val.params;
                     let cont =
-                        self.convert_lambda(def, Some((name.clone(), typ.clone())), arg_names);
+                        self.convert_lambda(def, Some((name.clone(), typ.clone())), arg_params);
 
-                    let label = T::make(&self, &Rc::downgrade(&cont), typ);
+                    let label = T::make(&self, name, &Rc::downgrade(&cont), typ)?;
                     self.labels
                         .insert(name.clone(), (typ.clone(), label.clone()));
-                    let rest = self.convert_expr(next);
+                    let rest = self.convert_expr(next)?;
 
                     refinement::Expr::Cont(cont, label, Box::new(rest))
                 }
                 Stmt::If(If { val, block: def }) => {
-                    let local = self.types.source.unwrap(val.convert(&self.vars));
-                    let rest = self.clone().convert_expr(next);
-                    let cont = self.convert_expr(def);
+                    let local = val.convert(&self.vars)?;
+                    let rest = self.clone().convert_expr(next)?;
+                    let cont = self.convert_expr(def)?;
                     refinement::Expr::Match(local, vec![rest, cont])
                 }
             },
         };
-        refinement::Spanned { span, val: expr }
+        Ok(refinement::Spanned { span, val: expr })
+    }
+
+    /// Call the function and bind the return values to the names.
+    fn convert_call(
+        &self,
+        names: &[String],
+        bind: &Bind,
+        next: &Rc<Spanned<Block>>,
+    ) -> Result<Expr<T>, ConvertErr> {
+        let Some(func_name) = &bind.func else {
+            let span = bind.args.span;
+            return Err(CallErr::Missing { span }.into());
+        };
+        let (func, args, rets) = self.func(func_name)?;
+        if names.len() != rets.len() {
+            return Err(CallErr::Arity {
+                span: func_name.span,
+                rets: rets.len(),
+                names: names.len(),
+            }
+            .into());
+        }
+
+        // the names are bound with the types of the return values
+        let params: Vec<_> = names
+            .iter()
+            .zip(rets)
+            .map(|(name, typ)| Param {
+                name: name.clone(),
+                typ,
+            })
+            .collect();
+
+        let arg = self.convert_value(&bind.args, &args)?;
+        let rest = self.convert_lambda(next, None, &params);
+        Ok(refinement::Expr::App(func, arg, rest))
     }
 
+    /// The arguments are bound to the parameters, the contracts are for the parameters.
     pub fn convert_lambda_inner(
         self,
-        names: &[String],
+        params: &[Param],
+        contracts: Vec<(String, NegTyp)>,
         labels: HashMap<String, WeakFuncDef<T>>,
         block: Rc<Spanned<Block>>,
-    ) -> refinement::Lambda<T, impl Fn(&[T]) -> refinement::Spanned<refinement::Expr<T>>> {
-        let names = names.to_owned();
-        let func = move |args: &[T]| -> refinement::Spanned<refinement::Expr<T>> {
+    ) -> refinement::Lambda<T, impl Fn(&[T]) -> refinement::Inst<T>> {
+        let params = params.to_owned();
+        let func = move |args: &[T]| -> refinement::Inst<T> {
             let mut this = self.clone();
             for (name, def) in &labels {
-                let label = T::make(&this, &def.weak, &def.typ);
+                let label = T::make(&this, name, &def.weak, &def.typ)?;
                 this.labels.insert(name.clone(), (def.typ.clone(), label));
             }
 
-            this.vars.extend(this.types.consume_args(args, &names));
+            let vars = this.types.consume_args(args, &params)?;
+            this.bind(vars);
+            this.contracts.extend(contracts.clone());
             this.convert_expr(&block)
         };
         refinement::Lambda {
@@ -159,11 +397,12 @@ impl<T: Val> Desugar<T> {
         &self,
         block: &Rc<Spanned<Block>>,
         label: Option<(String, NegTyp)>,
-        names: &[String],
+        params: &[Param],
     ) -> Rc<refinement::Lambda<T>> {
         let func = Rc::new_cyclic(|rec| {
             let mut labels = HashMap::new();
             let mut this = self.clone();
+            let mut contracts = vec![];
             if let Some((name, typ)) = label.as_ref() {
                 let weak_def = WeakFuncDef {
                     // magic to initialize dynamically sized recursive Rc
@@ -172,21 +411,43 @@ impl<T: Val> Desugar<T> {
                 };
                 labels.insert(name.clone(), weak_def);
                 this.ret = typ.ret.clone();
+                contracts = Self::contracts(&typ.args.val);
             }
-            this.convert_lambda_inner(names, labels, block.clone())
+            this.convert_lambda_inner(params, contracts, labels, block.clone())
         });
 
         func
     }
 }
 
+impl Block {
+    /// The values that are used by the first statement.
+    fn values(&self) -> Vec<&Value> {
+        match self {
+            Block::End(bind) => bind.args.val.iter().collect(),
+            Block::IfElse(If { val, .. }, _) | Block::Match(Match { val, .. }) => vec![val],
+            Block::Stmt { step, .. } => match &step.val {
+                Stmt::Let(Let { bind, .. }) | Stmt::Call(bind) => bind.args.val.iter().collect(),
+                Stmt::If(If { val, .. }) | Stmt::Assert(val) => vec![val],
+                Stmt::Debug | Stmt::FuncDef(_) => vec![],
+            },
+        }
+    }
+}
+
 struct Desugared<T: Val> {
+    types: types::DesugarTypes,
     funcs: HashMap<String, (Rc<Lambda<T>>, refinement::Fun<refinement::NegTyp>)>,
 }
 
 impl<T: Val> Desugared<T> {
-    fn new(list: NameList, source: MultiFile, m: &Module) -> Self {
-        let types = types::DesugarTypes::new(list, source);
+    fn new(list: NameList, m: &Module) -> Result<Self, ConvertErr> {
+        unique_names(m)?;
+        let mut types = types::DesugarTypes::new(list);
+        let consts = consts(m)?;
+        types.terms = nested(&consts);
+        types.data = data(m);
+        let consts = nested(&consts);
 
         let mut labels = HashMap::new();
         let mut funcs_uninit = HashMap::new();
@@ -202,20 +463,24 @@ impl<T: Val> Desugared<T> {
         }
 
         let mut funcs_init = HashMap::new();
+        let table = Rc::new(table(m));
 
         for def in &m.0 {
             if let Def::Func(func) = def {
                 let this = Desugar {
                     types: types.clone(),
-                    vars: HashMap::new(),
+                    vars: consts.clone(),
                     labels: HashMap::new(),
+                    contracts: HashMap::new(),
+                    table: table.clone(),
                     ret: func.typ.ret.clone(),
                 };
 
-                let neg = this.types.convert_neg(func.typ.clone());
+                let neg = this.types.convert_neg(func.typ.clone())?;
 
                 let lambda = this.clone().convert_lambda_inner(
-                    &func.typ.args.val.names,
+                    &func.typ.args.val.params,
+                    Desugar::<T>::contracts(&func.typ.args.val),
                     labels.clone(),
                     func.block.clone(),
                 );
@@ -228,8 +493,90 @@ impl<T: Val> Desugared<T> {
 
         assert!(funcs_uninit.is_empty());
 
-        Desugared { funcs: funcs_init }
+        Ok(Desugared {
+            types,
+            funcs: funcs_init,
+        })
+    }
+}
+
+/// Constants are evaluated in the order of their definition, so they can use earlier constants.
+/// The name of a data segment is a constant with its address.
+fn consts(m: &Module) -> Result<HashMap<String, Int>, ConvertErr> {
+    let mut consts = HashMap::new();
+    let mut addr = 0;
+    for def in &m.0 {
+        let (name, val) = match def {
+            Def::Const(def) => {
+                if let Some(effect) = def.val.effects().first() {
+                    return Err(ConstErr::Effect {
+                        span: effect.span(),
+                    }
+                    .into());
+                }
+                let val = def.val.convert(&nested(&consts))?;
+                let span = def.name.span;
+                (&def.name, val.eval().map_err(|_| ConstErr::Div { span })?)
+            }
+            Def::Data(def) => {
+                let val = Int::new(addr, 32);
+                addr += def.data.bytes().len() as i64;
+                (&def.name, val)
+            }
+            _ => continue,
+        };
+        if consts.insert(name.val.clone(), val).is_some() {
+            let (span, name) = (name.span, name.val.clone());
+            return Err(DuplicateErr { span, name }.into());
+        }
+    }
+    Ok(consts)
+}
+
+fn nested<T>(consts: &HashMap<String, Int>) -> HashMap<String, Nested<T>> {
+    let nested = consts.iter().map(|(name, val)| {
+        let val = Nested::Const(val.signed(), val.size);
+        (name.clone(), val)
+    });
+    nested.collect()
+}
+
+/// Functions and types can have the same name, but imported files can not redefine them.
+fn unique_names(m: &Module) -> Result<(), DuplicateErr> {
+    let (mut funcs, mut typs) = (HashSet::new(), HashSet::new());
+    for def in &m.0 {
+        let (new, name, span) = match def {
+            Def::Func(func) => (funcs.insert(&func.name), &func.name, func.typ.args.span),
+            Def::Typ(named) => (typs.insert(&named.name), &named.name, named.typ.span),
+            _ => continue,
+        };
+        if !new {
+            let name = name.clone();
+            return Err(DuplicateErr { span, name });
+        }
     }
+    Ok(())
+}
+
+/// Data segments are laid out one after another at the start of memory.
+fn data(m: &Module) -> Rc<[u8]> {
+    let segments = m.0.iter().filter_map(|def| match def {
+        Def::Data(def) => Some(def.data.bytes()),
+        _ => None,
+    });
+    segments.flatten().collect()
+}
+
+/// Function pointers index into the top-level functions in the order of their definition.
+fn table(m: &Module) -> HashMap<String, (usize, NegTyp)> {
+    let funcs = m.0.iter().filter_map(|def| match def {
+        Def::Func(func) => Some((func.name.clone(), func.typ.clone())),
+        _ => None,
+    });
+    let table = funcs
+        .enumerate()
+        .map(|(idx, (name, typ))| (name, (idx, typ)));
+    table.collect()
 }
 
 impl NameList {
@@ -239,9 +586,16 @@ impl NameList {
         for def in &m.0 {
             match def {
                 Def::Func(_func) => {}
+                Def::Import(_path) => {}
+                Def::Const(_def) => {}
+                Def::Data(_def) => {}
                 Def::Typ(named) => {
-                    let NamedConstraint { name, typ } = named.clone();
-                    list.0.insert(name, Named::new(typ));
+                    let NamedConstraint {
+                        name,
+                        generics,
+                        typ,
+                    } = named.clone();
+                    list.insert(name, typ, generics);
                 }
             }
         }
@@ -250,46 +604,101 @@ impl NameList {
     }
 }
 
-pub fn check(source: &MultiFile) {
-    let m = &source.get_module();
+/// Check all top-level functions and report the errors of all of them.
+/// Functions are checked in the order of their definition, which is their order in the source.
+pub fn check(source: &MultiFile) -> Result<(), Report> {
+    let m = &source.get_module()?;
     let list = NameList::new(m);
-    let this = Desugared::new(list, source.clone(), m);
+    let this = Desugared::new(list, m).map_err(|err| source.report(err))?;
 
-    for (lambda, neg) in this.funcs.values() {
+    let mut errors = vec![];
+    for def in &m.0 {
+        let Def::Func(func) = def else { continue };
+        let (lambda, neg) = &this.funcs[&func.name];
         let ctx = refinement::SubContext::default();
-        source.unwrap(ctx.check_expr(lambda, neg));
+        if let Err(err) = ctx.check_expr(lambda, neg) {
+            errors.push(source.report(err));
+        }
+    }
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Report::from(CheckErrs { errors })),
     }
 }
 
-pub fn run(source: MultiFile, name: &str, args: Vec<i32>, heap: Vec<u8>) -> Vec<i32> {
-    let m = source.get_module();
-
+/// Asserts are checked at runtime with [AssertMode::Checked], this is useful for unchecked programs.
+/// The highest bit of each result is its sign.
+/// The heap is placed in memory after the data segments, the checker makes sure
+/// that the arguments of a function never own memory in the data segments.
+pub fn run(
+    source: MultiFile,
+    name: &str,
+    args: Vec<i64>,
+    heap: Vec<u8>,
+    asserts: AssertMode,
+) -> Result<Vec<i64>, Report> {
+    let m = source.get_module()?;
     let list = NameList::new(&m);
-    let this = Desugared::<i32>::new(list, source, &m);
+    let this = Desugared::<Int>::new(list, &m).map_err(|err| source.report(err))?;
+
+    let Some((lambda, typ)) = this.funcs.get(name) else {
+        return Err(source.report(RunErr::UnknownFunc(name.to_owned())));
+    };
+    if args.len() != typ.tau.len() {
+        let (have, need) = (args.len(), typ.tau.len());
+        return Err(source.report(RunErr::NumArgs { have, need }));
+    }
+    let args: Vec<_> = zip_eq(args, &typ.tau)
+        .map(|(arg, (size, _))| Int::new(arg, *size))
+        .collect();
+
+    let mut table = vec![];
+    for def in &m.0 {
+        if let Def::Func(func) = def {
+            table.push(this.funcs[&func.name].0.clone());
+        }
+    }
 
-    let (lambda, _typ) = &this.funcs[name];
+    let mut data = this.types.data.to_vec();
+    data.extend(heap);
 
-    let expr = lambda.inst(&args);
-    let mut memory = refinement::eval::Memory::new(heap);
-    memory.eval(expr.val)
+    let mut memory = refinement::eval::Memory::new(data, asserts, table);
+    let res = lambda
+        .inst(&args)
+        .map_err(EvalErr::from)
+        .and_then(|expr| memory.eval(expr.val));
+    let res = res.map_err(|err| source.report(err))?;
+    Ok(res.iter().map(Int::signed).collect())
+}
+
+pub fn compile(source: MultiFile) -> Result<wasm::Module, Report> {
+    let m = source.get_module()?;
+    let list = NameList::new(&m);
+    let lower = || {
+        let this = Desugared::<wasm::Var>::new(list, &m)?;
+
+        let mut lower = wasm::Lower::default();
+        for def in &m.0 {
+            if let Def::Func(func) = def {
+                let (lambda, _typ) = &this.funcs[&func.name];
+                let args = this.types.sizes(&func.typ.args.val.params)?;
+                let rets = this.types.sizes(&func.typ.ret.val.params)?;
+                lower.export(&func.name, lambda, args, rets);
+            }
+        }
+        lower.finish(this.types.data.to_vec())
+    };
+    lower().map_err(|err| source.report(err))
 }
 
 pub fn convert_neg(files: &[&'static str], idx: usize) -> refinement::Fun<refinement::NegTyp> {
     let offset = files.iter().take(idx).map(|x| x.len()).sum();
     let lexer = Lexer::new(files[idx], offset);
-    let parsed = NegTypParser::new().parse(lexer).unwrap();
-
-    let desugar = types::DesugarTypes {
-        named: NameList(Default::default()),
-        terms: Default::default(),
-        exactly: Default::default(),
-        source: MultiFile {
-            builtin: files.to_owned(),
-            code: String::new(),
-            path: String::new(),
-        },
-    };
-    desugar.convert_neg(parsed)
+    let parsed = NegTypParser::new().parse(&mut vec![], lexer).unwrap();
+
+    let desugar = types::DesugarTypes::new(NameList::default());
+    desugar.convert_neg(parsed).unwrap()
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -298,3 +707,176 @@ pub struct ScopeErr {
     #[label = "The variable"]
     span: SourceSpan,
 }
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum ConstErr {
+    #[error("The constant divides by zero")]
+    Div {
+        #[label = "The constant"]
+        span: SourceSpan,
+    },
+    #[error("Constants can not call functions, read arrays or take function pointers")]
+    Effect {
+        #[label = "Not a constant"]
+        span: SourceSpan,
+    },
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The name `{name}` is already defined")]
+pub struct DuplicateErr {
+    #[label = "The second definition"]
+    span: SourceSpan,
+    name: String,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum WidthErr {
+    #[error("Can not combine values of {l} and {r} bits")]
+    #[diagnostic(help("Use `as` or `as$` to zero or sign extend the smaller value"))]
+    Mixed {
+        #[label = "The left value"]
+        l_span: Option<SourceSpan>,
+        #[label = "The right value"]
+        r_span: Option<SourceSpan>,
+        l: u32,
+        r: u32,
+    },
+    #[error("Can not extend a value of {have} bits to {need} bits")]
+    Narrow {
+        #[label = "The extension"]
+        span: SourceSpan,
+        have: u32,
+        need: u32,
+    },
+    #[error("The literal {val} does not fit in {size} bits")]
+    Literal {
+        #[label = "The value"]
+        span: Option<SourceSpan>,
+        val: i128,
+        size: u32,
+    },
+    #[error("The resource takes {need} bits, but the argument has {have} bits")]
+    Arg {
+        #[label = "The resource"]
+        span: SourceSpan,
+        have: u32,
+        need: u32,
+    },
+}
+
+/// Errors in the program that are found while it is converted for checking, running or compiling.
+/// Values are converted after their variables are bound.
+#[derive(Error, Diagnostic, Debug)]
+pub enum ConvertErr {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Scope(#[from] ScopeErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Width(#[from] WidthErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Call(#[from] CallErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    TypArgs(#[from] TypArgsErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Pattern(#[from] PatternErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Args(#[from] ArgsErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Const(#[from] ConstErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Duplicate(#[from] DuplicateErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Read(#[from] ReadErr),
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Found type errors in {} functions", errors.len())]
+pub struct CheckErrs {
+    #[related]
+    errors: Vec<Report>,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum RunErr {
+    #[error("There is no function named `{0}`")]
+    UnknownFunc(String),
+    #[error("The function takes {need} arguments, but {have} were given")]
+    NumArgs { have: usize, need: usize },
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum ReadErr {
+    #[error("Arrays can only be indexed once")]
+    #[diagnostic(help("Read the inner array into a variable first"))]
+    Nested {
+        #[label = "The array"]
+        span: SourceSpan,
+    },
+    #[error(
+        "Reads are done before the statement, so they can not be on the right of `&&` or `||`"
+    )]
+    Conditional {
+        #[label = "The read"]
+        span: SourceSpan,
+    },
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum CallErr {
+    #[error("Functions can not be used in types")]
+    InType {
+        #[label = "The call"]
+        span: SourceSpan,
+    },
+    #[error("Only functions with one return value can be called in a value")]
+    NumRets {
+        #[label = "The call"]
+        span: SourceSpan,
+    },
+    #[error("The function returns {rets} values, but {names} names are bound")]
+    Arity {
+        #[label = "The call"]
+        span: SourceSpan,
+        rets: usize,
+        names: usize,
+    },
+    #[error("A `let` has to call a function")]
+    #[diagnostic(help("Use the values directly instead of binding them to names"))]
+    Missing {
+        #[label = "The values"]
+        span: SourceSpan,
+    },
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The type takes {expected} type arguments")]
+pub struct TypArgsErr {
+    #[label = "The type"]
+    span: SourceSpan,
+    expected: usize,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Expected {need} values, but {have} were given")]
+pub struct ArgsErr {
+    #[label = "The values"]
+    pub span: Option<SourceSpan>,
+    pub have: usize,
+    pub need: usize,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Only the last match arm can be `_`, and it has to be there")]
+pub struct PatternErr {
+    #[label = "The pattern"]
+    span: SourceSpan,
+}
@@ -1,118 +1,325 @@
-use crate::error::MultiFile;
 use crate::parse::expr::{Spanned, Value};
-use crate::parse::types::{Constraint, NegTyp, PosTyp, Prop};
+use crate::parse::types::{Constraint, NegTyp, Param, ParamTyp, PosTyp, ResourceTyp};
 use crate::refinement::heap::{ConsumeErr, Heap};
 use crate::refinement::{func_term::FuncTerm, term::Term, typing::zip_eq, Resource};
-use crate::{refinement, Nested};
+use crate::{refinement, FuncNested, Nested, Struct};
 
-use std::collections::HashMap;
+use miette::SourceSpan;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
 
-use super::value::IntoScope;
+use super::value::{Effect, IntoScope};
+use super::{ArgsErr, CallErr, ConvertErr, TypArgsErr, WidthErr};
 
 #[derive(Clone)]
 pub struct Named {
     pub id: usize,
+    pub generics: Vec<String>,
     pub typ: Rc<Spanned<PosTyp>>,
 }
 
-impl Named {
-    pub fn new(typ: Rc<Spanned<PosTyp>>) -> Self {
-        static NAME_ID: AtomicUsize = AtomicUsize::new(0);
-        Self {
-            id: NAME_ID.fetch_add(1, Ordering::Relaxed),
-            typ,
+type Instance = (usize, Vec<Option<usize>>);
+
+#[derive(Clone, Default)]
+pub struct NameList {
+    pub names: HashMap<String, Named>,
+    // ids are only unique within one module
+    next_id: Rc<Cell<usize>>,
+    instances: Rc<RefCell<BTreeMap<Instance, usize>>>,
+}
+
+impl NameList {
+    pub fn insert(&mut self, name: String, typ: Rc<Spanned<PosTyp>>, generics: Vec<String>) {
+        let id = self.fresh_id();
+        self.names.insert(name, Named { id, generics, typ });
+    }
+
+    fn fresh_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// Every instantiation of a generic named type is a separate resource.
+    fn instance_id(&self, named: &Named, args: &[Resource]) -> usize {
+        if args.is_empty() {
+            return named.id;
         }
+        let key = (named.id, args.iter().map(Resource::val_typ).collect());
+        let mut instances = self.instances.borrow_mut();
+        *instances.entry(key).or_insert_with(|| self.fresh_id())
     }
 }
 
-#[derive(Clone, Default)]
-pub struct NameList(pub HashMap<String, Named>);
-
 #[derive(Clone)]
 pub struct DesugarTypes {
     pub(super) named: NameList,
     pub terms: HashMap<String, Nested<Term>>,
+    // the data segments, they start at address zero
+    pub data: Rc<[u8]>,
     pub exactly: HashMap<String, Exactly>,
-    pub source: MultiFile,
+    // parts of a named type are moved or inspected together with the named type
+    pub in_named: bool,
+    // the resources that are passed for the generics of a named type
+    pub generics: HashMap<String, Resource>,
 }
 
 type Exactly = Rc<dyn Fn(&mut dyn Heap) -> Result<(), ConsumeErr>>;
 
 impl DesugarTypes {
-    pub(super) fn new(list: NameList, source: MultiFile) -> Self {
+    pub(super) fn new(list: NameList) -> Self {
         Self {
             named: list,
             terms: HashMap::new(),
+            data: Rc::new([]),
             exactly: HashMap::new(),
-            source,
+            in_named: false,
+            generics: HashMap::new(),
+        }
+    }
+
+    pub fn tau(&self, params: &[Param]) -> Result<Vec<(u32, String)>, ConvertErr> {
+        let mut tau = vec![];
+        for param in params {
+            match &param.typ {
+                ParamTyp::Int(size) => tau.push((*size, param.name.clone())),
+                ParamTyp::Custom(typ) => {
+                    let named = self.named.names.try_get(&typ.name)?;
+                    let fields = self.tau(&named.typ.val.params)?;
+                    let fields = fields.into_iter().map(|(size, field)| {
+                        let name = format!("{}.{field}", param.name);
+                        (size, name)
+                    });
+                    tau.extend(fields);
+                }
+            }
         }
+        Ok(tau)
+    }
+
+    /// The number of values that are needed for the parameters.
+    pub fn arity(&self, params: &[Param]) -> Result<usize, ConvertErr> {
+        Ok(self.tau(params)?.len())
     }
 
-    pub fn tau(&self, names: &[String]) -> Vec<(u32, String)> {
-        names.iter().map(|name| (32, name.clone())).collect()
+    /// The number of bits of each value that is needed for the parameters.
+    pub fn sizes(&self, params: &[Param]) -> Result<Vec<u32>, ConvertErr> {
+        let tau = self.tau(params)?.into_iter();
+        Ok(tau.map(|(size, _)| size).collect())
     }
 
-    pub fn consume_args<T: Clone>(&self, args: &[T], names: &[String]) -> Vec<(String, Nested<T>)> {
-        zip_eq(names, args)
-            .map(|(name, arg)| (name.clone(), Nested::Just(arg.clone())))
-            .collect()
+    pub fn consume_args<T: Clone + 'static>(
+        &self,
+        args: &[T],
+        params: &[Param],
+    ) -> Result<Vec<(String, Nested<T>)>, ConvertErr> {
+        let need = self.arity(params)?;
+        if args.len() != need {
+            let have = args.len();
+            return Err(ArgsErr {
+                span: None,
+                have,
+                need,
+            }
+            .into());
+        }
+
+        let mut args = args;
+        let mut res = vec![];
+        for param in params {
+            let nested = match &param.typ {
+                ParamTyp::Int(_) => {
+                    let (arg, rest) = args.split_first().unwrap();
+                    args = rest;
+                    Nested::Just(arg.clone())
+                }
+                ParamTyp::Custom(typ) => {
+                    let named = self.named.names.try_get(&typ.name)?;
+                    let (fields, rest) = args.split_at(self.arity(&named.typ.val.params)?);
+                    args = rest;
+
+                    // the types of the fields were found by `arity`, so this can not fail
+                    let (this, typ) = (self.clone(), named.typ.clone());
+                    let val = Rc::new(move |fields: &[T]| Struct {
+                        map: (this.consume_args(fields, &typ.val.params).unwrap())
+                            .into_iter()
+                            .collect(),
+                    });
+                    Nested::Resource(FuncNested { val }, fields.to_vec())
+                }
+            };
+            res.push((param.name.clone(), nested));
+        }
+        Ok(res)
     }
 
-    pub fn consume_terms(&mut self, terms: &[Term], names: &[String]) -> Result<(), ConsumeErr> {
-        self.terms.extend(self.consume_args(terms, names));
+    pub fn consume_terms(&mut self, terms: &[Term], params: &[Param]) -> Result<(), ConsumeErr> {
+        self.terms.extend(self.consume_args(terms, params)?);
 
         Ok(())
     }
 
-    pub fn convert_pos(&self, pos: Rc<Spanned<PosTyp>>) -> refinement::Fun<refinement::PosTyp> {
+    /// Parameters with a custom type own the resource of that type.
+    pub fn convert_params(&self, params: &[Param], heap: &mut dyn Heap) -> Result<(), ConsumeErr> {
+        for param in params {
+            let ParamTyp::Custom(typ) = &param.typ else {
+                continue;
+            };
+            let switch = refinement::Switch {
+                resource: self.get_resource(typ)?,
+                moved: true,
+                args: self.terms[&param.name].flatten(),
+                span: Some(typ.name.span),
+                cond: Term::bool(true),
+            };
+            heap.apply(Box::new(move |heap| heap.once(switch)))?;
+        }
+        Ok(())
+    }
+
+    /// Every function can inspect the data segments, their contents never change.
+    fn convert_data(&self, heap: &mut dyn Heap) -> Result<(), ConsumeErr> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        heap.exactly(self.data_forall(), false)
+    }
+
+    /// The arguments can not own memory in the data segments,
+    /// otherwise the function could change them.
+    fn check_data(&self, heap: &mut dyn Heap, args: SourceSpan) -> Result<(), ConsumeErr> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        heap.outside(self.data_forall().have, args)
+    }
+
+    /// The data segments start at address zero.
+    fn data_forall(&self) -> refinement::CtxForall {
+        let (data, len) = (self.data.clone(), Term::nat(self.data.len() as i64, 32));
+        refinement::CtxForall {
+            have: refinement::Forall {
+                resource: Resource::Owned,
+                mask: FuncTerm::new(move |idx| idx[0].ult(&len)),
+                span: None,
+            },
+            value: FuncTerm::new(move |idx| {
+                let mut val = Term::nat(0, 8).to_bv();
+                for (addr, byte) in data.iter().enumerate().rev() {
+                    let here = idx[0].eq(&Term::nat(addr as i64, 32)).to_bool();
+                    val = here.ite(&Term::nat(*byte as i64, 8).to_bv(), &val);
+                }
+                Term::BV(val)
+            }),
+            borrowed: true,
+        }
+    }
+
+    pub fn convert_pos(
+        &self,
+        pos: Rc<Spanned<PosTyp>>,
+    ) -> Result<refinement::Fun<refinement::PosTyp>, ConvertErr> {
         let this = self.clone();
-        refinement::Fun {
-            tau: this.tau(&pos.val.names),
+        Ok(refinement::Fun {
+            tau: this.tau(&pos.val.params)?,
             span: Some(pos.span),
             fun: Rc::new(move |heap, terms| {
                 let mut this = this.clone();
 
-                this.consume_terms(terms, &pos.val.names)?;
+                this.consume_terms(terms, &pos.val.params)?;
+                this.convert_params(&pos.val.params, heap)?;
                 this.convert_constraint(&pos.val.parts, heap)?;
 
                 Ok(refinement::PosTyp)
             }),
-        }
+        })
     }
 
-    pub fn convert_neg(&self, neg: NegTyp) -> refinement::Fun<refinement::NegTyp> {
+    pub fn convert_neg(
+        &self,
+        neg: NegTyp,
+    ) -> Result<refinement::Fun<refinement::NegTyp>, ConvertErr> {
         let NegTyp { args, ret } = neg;
 
         let this = self.clone();
-        refinement::Fun {
-            tau: this.tau(&args.val.names),
+        Ok(refinement::Fun {
+            tau: this.tau(&args.val.params)?,
             span: Some(args.span),
             fun: Rc::new(move |heap, terms| {
                 let mut this = this.clone();
 
-                this.consume_terms(terms, &args.val.names)?;
+                this.consume_terms(terms, &args.val.params)?;
+                this.convert_params(&args.val.params, heap)?;
+                this.convert_data(heap)?;
                 this.convert_constraint(&args.val.parts, heap)?;
+                this.check_data(heap, args.span)?;
 
                 Ok(refinement::NegTyp {
                     arg: refinement::PosTyp,
-                    ret: this.convert_pos(ret.clone()),
+                    ret: this.convert_pos(ret.clone())?,
                 })
             }),
-        }
+        })
     }
 
-    pub fn convert_prop(&self, prop: &Prop) -> Term {
-        self.source.unwrap(prop.convert(&self.terms)).make_term()
+    pub fn convert_val(&self, val: &Value) -> Result<Term, ConvertErr> {
+        Ok(val.convert(&self.terms)?.make_term())
     }
 
-    pub fn convert_val(&self, val: &Value) -> Term {
-        self.source.unwrap(val.convert(&self.terms)).make_term()
+    pub fn convert_vals(&self, vals: &[Value]) -> Result<Vec<Term>, ConvertErr> {
+        vals.iter().map(|x| self.convert_val(x)).collect()
     }
 
-    pub fn convert_vals(&self, vals: &[Value]) -> Vec<Term> {
-        vals.iter().map(|x| self.convert_val(x)).collect()
+    /// Literals in the arguments of a resource take the sizes of its parameters.
+    fn convert_args(
+        &self,
+        vals: &[Value],
+        resource: &Resource,
+        span: SourceSpan,
+    ) -> Result<Vec<Term>, ConvertErr> {
+        let sizes: Vec<_> = match resource {
+            Resource::Named(name) => name.typ.tau.iter().map(|(size, _)| *size).collect(),
+            Resource::Owned => vec![32],
+        };
+        if sizes.len() != vals.len() {
+            return self.convert_vals(vals);
+        }
+        let mut args = vec![];
+        for (val, need) in vals.iter().zip(sizes) {
+            let free = val.convert(&self.terms)?.fit(need, Some(span))?;
+            let have = free.size();
+            if have != need {
+                return Err(WidthErr::Arg { span, have, need }.into());
+            }
+            args.push(free.make_term());
+        }
+        Ok(args)
+    }
+
+    /// Array reads inspect the byte and bind its value.
+    fn convert_reads(&mut self, values: &[&Value], heap: &mut dyn Heap) -> Result<(), ConsumeErr> {
+        for effect in values.iter().flat_map(|val| val.effects()) {
+            let read = match &effect {
+                Effect::Read(read) => read,
+                Effect::Call(_) | Effect::FuncPtr(_) => {
+                    let span = effect.span();
+                    return Err(ConvertErr::from(CallErr::InType { span }).into());
+                }
+            };
+            let addr = read.addr(&self.terms)?.make_term();
+            let switch = refinement::Switch {
+                resource: Resource::Owned,
+                moved: false,
+                args: vec![addr.clone()],
+                span: Some(effect.span()),
+                cond: Term::bool(true),
+            };
+            let res = heap.apply(Box::new(move |heap| heap.once(switch)))?;
+            let val = res.get_byte(&[addr]).extend_to(32);
+            self.terms.insert(effect.key(), Nested::Just(val));
+        }
+        Ok(())
     }
 
     pub fn convert_constraint(
@@ -121,45 +328,65 @@ impl DesugarTypes {
         heap: &mut dyn Heap,
     ) -> Result<(), ConsumeErr> {
         for part in parts {
+            self.convert_reads(&part.val.values(), heap)?;
             match &part.val {
                 Constraint::Let(new_name, val) => {
-                    let value = self.convert_val(val);
+                    let value = self.convert_val(val)?;
                     self.terms.insert(new_name.clone(), Nested::Just(value));
                 }
                 Constraint::Forall(forall) => {
+                    let moved = forall.access.moved() || self.in_named;
                     let cond = forall.cond.clone();
                     let names = forall.names.clone();
+                    let resource = self.get_resource(&forall.named)?;
 
                     let this = self.clone();
+                    let mask = move |terms: &[Term]| {
+                        let terms = terms.iter().cloned().map(Nested::Just);
+                        let mut this = this.clone();
+                        this.terms.extend(zip_eq(names.clone(), terms));
+                        this.convert_val(&cond)
+                    };
+                    // the condition is converted once here to find errors in it,
+                    // after that it only changes the terms of the names
+                    let sizes = resource.arg_sizes();
+                    if sizes.len() != forall.names.len() {
+                        let (have, need) = (forall.names.len(), sizes.len());
+                        let span = Some(part.span);
+                        return Err(ConvertErr::from(ArgsErr { span, have, need }).into());
+                    }
+                    let sizes = sizes.into_iter();
+                    let fresh: Vec<_> =
+                        sizes.map(|(size, name)| Term::fresh(&name, size)).collect();
+                    mask(&fresh)?;
+
                     let forall = refinement::Forall {
-                        resource: self.get_resource(&forall.named),
+                        resource,
                         span: Some(part.span),
-                        mask: FuncTerm::new_bool(move |terms| {
-                            let terms = terms.iter().cloned().map(Nested::Just);
-
-                            let mut this = this.clone();
-                            this.terms.extend(zip_eq(names.clone(), terms));
-                            this.convert_prop(&cond).to_bool()
-                        }),
+                        mask: FuncTerm::new_bool(move |terms| mask(terms).unwrap().to_bool()),
                     };
 
-                    heap.forall(forall)?;
+                    heap.forall(forall, moved)?;
                 }
                 Constraint::Assert(cond) => {
-                    heap.assert(self.convert_prop(cond), Some(part.span))?;
+                    heap.assert(self.convert_val(cond)?, Some(part.span))?;
                 }
                 Constraint::Switch(new_name, switch) => {
-                    let args = self.convert_vals(&switch.args);
                     let cond = switch.cond.as_ref();
-                    let resource = self.get_resource(&switch.named);
+                    let resource = self.get_resource(&switch.named)?;
+                    let args =
+                        self.convert_args(&switch.args, &resource, switch.named.name.span)?;
 
+                    let moved = switch.access.moved() || self.in_named;
                     let switch = refinement::Switch {
                         resource,
+                        moved,
                         args,
                         span: Some(part.span),
-                        cond: cond
-                            .map(|v| self.convert_val(v))
-                            .unwrap_or(Term::bool(true)),
+                        cond: match cond {
+                            Some(cond) => self.convert_val(cond)?,
+                            None => Term::bool(true),
+                        },
                     };
 
                     let switch_clone = switch.clone();
@@ -175,7 +402,7 @@ impl DesugarTypes {
 
                         let equal = Rc::new(move |h: &mut dyn Heap| {
                             for got in res.removals.clone() {
-                                h.exactly(got)?;
+                                h.exactly(got, moved)?;
                             }
                             Ok(())
                         });
@@ -183,29 +410,73 @@ impl DesugarTypes {
                     }
                 }
                 Constraint::Exactly(name) => {
-                    let equal = self.source.unwrap(self.exactly.try_get(name));
+                    let equal = self.exactly.try_get(name).map_err(ConvertErr::from)?;
                     equal(heap)?;
                 }
+                Constraint::Func(name, typ) => {
+                    let ptr = self.convert_val(&Value::Var(name.clone(), vec![]))?;
+                    heap.func(ptr, self.convert_neg(typ.clone())?)?;
+                }
             }
         }
 
         Ok(())
     }
 
-    pub fn convert_named(&self, named: &Named) -> refinement::Name {
-        refinement::Name {
-            id: named.id,
-            typ: self.convert_pos(named.typ.clone()),
-        }
+    pub fn convert_named(
+        &self,
+        named: &Named,
+        args: Vec<Resource>,
+    ) -> Result<refinement::Name, ConvertErr> {
+        let mut this = self.clone();
+        this.in_named = true;
+        let id = self.named.instance_id(named, &args);
+        this.generics = named.generics.iter().cloned().zip(args).collect();
+        Ok(refinement::Name {
+            id,
+            typ: this.convert_pos(named.typ.clone())?,
+        })
     }
 
-    pub fn get_resource(&self, name: &Spanned<String>) -> Resource {
+    pub fn get_resource(&self, typ: &ResourceTyp) -> Result<Resource, ConvertErr> {
+        let ResourceTyp { name, args } = typ;
+        if let Some(resource) = self.generics.get(&name.val) {
+            check_typ_args(typ, 0)?;
+            return Ok(resource.clone());
+        }
         match &*name.val {
-            "@byte" => Resource::Owned,
+            "@byte" => {
+                check_typ_args(typ, 0)?;
+                Ok(Resource::Owned)
+            }
             _ => {
-                let named = self.source.unwrap(self.named.0.try_get(name));
-                Resource::Named(self.convert_named(named))
+                let named = self.named.names.try_get(name)?;
+                check_typ_args(typ, named.generics.len())?;
+                let args = args.iter().map(|arg| self.get_resource(arg));
+                let args = args.collect::<Result<_, _>>()?;
+                Ok(Resource::Named(self.convert_named(named, args)?))
             }
         }
     }
 }
+
+fn check_typ_args(typ: &ResourceTyp, expected: usize) -> Result<(), TypArgsErr> {
+    if typ.args.len() != expected {
+        return Err(TypArgsErr {
+            span: typ.name.span,
+            expected,
+        });
+    }
+    Ok(())
+}
+
+impl Constraint {
+    /// The values outside of quantifiers, array reads are only allowed in these.
+    fn values(&self) -> Vec<&Value> {
+        match self {
+            Constraint::Let(_, val) | Constraint::Assert(val) => vec![val],
+            Constraint::Switch(_, switch) => switch.args.iter().chain(&switch.cond).collect(),
+            Constraint::Forall(_) | Constraint::Exactly(_) | Constraint::Func(..) => vec![],
+        }
+    }
+}
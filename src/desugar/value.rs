@@ -1,41 +1,258 @@
-use crate::parse::expr::{BinOp, BinOpValue, Index, Spanned, Value};
+use crate::parse::expr::{BinOp, BinOpValue, Bind, Extend, Index, Match, Spanned, Value};
 use crate::parse::types::{Prop, PropOp};
+use crate::refinement::{Free, Val};
 use crate::{refinement, Nested};
+use miette::SourceSpan;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use super::ScopeErr;
+use super::{ConvertErr, ReadErr, ScopeErr, WidthErr};
 
 impl Value {
     // convert a value to individual fields
-    pub fn convert<T: Clone>(
+    pub fn convert<T: Val>(
         &self,
         lookup: &HashMap<String, Nested<T>>,
-    ) -> Result<refinement::Free<T>, ScopeErr> {
-        let res = match self {
-            Value::Var(name, rest) => {
-                let mut curr = lookup.try_get(name)?;
-                let mut inner;
-                for r in rest {
-                    inner = curr.unwrap_more();
-                    let Index::Attribute(attr) = r else { panic!() };
-                    curr = inner.map.try_get(attr)?;
+    ) -> Result<refinement::Free<T>, ConvertErr> {
+        let (nested, span) = match self {
+            Value::Var(name, rest) => (Self::get(name, rest, lookup)?, name.span),
+            Value::Int(lit) => return Ok(literal(lit.val as i128)),
+            Value::Neg(val) => {
+                if let Value::Int(lit) = &**val {
+                    return Ok(literal(-(lit.val as i128)));
                 }
-                refinement::Free::Var(curr.unwrap_just().clone())
+                let val = val.convert(lookup)?;
+                let zero = refinement::Free::Just(0, val.size());
+                return Ok(refinement::BinOp::Sub.free(zero, val));
+            }
+            Value::Not(val) => {
+                let val = val.convert(lookup)?;
+                let ones = refinement::Free::Just(-1, val.size());
+                return Ok(refinement::BinOp::BitXor.free(val, ones));
             }
-            Value::Int32(val) => refinement::Free::Just(*val as i64, 32),
-            Value::BinOp(binop) => binop.convert(lookup)?,
-            Value::Prop(prop) => prop.convert(lookup)?,
+            Value::BinOp(binop) => return binop.convert(lookup),
+            Value::Prop(prop) => return prop.convert(lookup),
+            Value::Extend(extend) => return extend.convert(lookup),
+            Value::Call(bind) => (
+                Self::get_bound(&Effect::Call(bind), lookup)?,
+                call_span(bind),
+            ),
+            Value::FuncPtr(name) => (Self::get_bound(&Effect::FuncPtr(name), lookup)?, name.span),
+        };
+        let res = match nested {
+            Nested::Just(val) => refinement::Free::Var(val),
+            Nested::Const(val, size) => refinement::Free::Just(val as i128, size),
+            Nested::Resource(..) => return Err(ScopeErr { span }.into()),
         };
         Ok(res)
     }
+
+    /// Like `convert`, but a resource is converted to all of its fields.
+    pub fn convert_all<T: Val>(
+        &self,
+        lookup: &HashMap<String, Nested<T>>,
+    ) -> Result<Vec<refinement::Free<T>>, ConvertErr> {
+        let nested = match self {
+            Value::Var(name, rest) => match Self::get(name, rest, lookup)? {
+                Nested::Const(..) => return Ok(vec![self.convert(lookup)?]),
+                nested => nested,
+            },
+            Value::Call(bind) => Self::get_bound(&Effect::Call(bind), lookup)?,
+            _ => return Ok(vec![self.convert(lookup)?]),
+        };
+        let fields = nested.flatten().into_iter();
+        Ok(fields.map(refinement::Free::Var).collect())
+    }
+
+    // the results of calls are bound before the value is converted
+    fn get_bound<T: Clone>(
+        effect: &Effect,
+        lookup: &HashMap<String, Nested<T>>,
+    ) -> Result<Nested<T>, ScopeErr> {
+        let key = Spanned {
+            span: effect.span(),
+            val: effect.key(),
+        };
+        Ok(lookup.try_get(&key)?.clone())
+    }
+
+    fn get<T: Clone>(
+        name: &Spanned<String>,
+        rest: &[Index],
+        lookup: &HashMap<String, Nested<T>>,
+    ) -> Result<Nested<T>, ScopeErr> {
+        // the value of an array read is bound before the value is converted
+        if let Some(Index::Value(_)) = rest.last() {
+            let key = Spanned {
+                span: name.span,
+                val: read_key(name),
+            };
+            return Ok(lookup.try_get(&key)?.clone());
+        }
+
+        let mut curr = lookup.try_get(name)?.clone();
+        for r in rest {
+            let Index::Attribute(attr) = r else {
+                return Err(ScopeErr { span: name.span });
+            };
+            let Nested::Resource(..) = curr else {
+                return Err(ScopeErr { span: attr.span });
+            };
+            curr = curr.unwrap_more().map.try_get(attr)?.clone();
+        }
+        Ok(curr)
+    }
+
+    /// All array reads and calls in the value in evaluation order,
+    /// effects in the arguments come before the effect itself.
+    pub fn effects(&self) -> Vec<Effect<'_>> {
+        match self {
+            Value::Var(name, rest) => {
+                let Some((Index::Value(idx), base)) = rest.split_last() else {
+                    return vec![];
+                };
+                let mut effects = idx.effects();
+                effects.push(Effect::Read(Read { name, base, idx }));
+                effects
+            }
+            Value::Int(_) => vec![],
+            Value::Neg(val) | Value::Not(val) => val.effects(),
+            Value::FuncPtr(name) => vec![Effect::FuncPtr(name)],
+            Value::Extend(extend) => extend.val.effects(),
+            Value::BinOp(binop) => [binop.l.effects(), binop.r.effects()].concat(),
+            Value::Prop(prop) => [prop.l.effects(), prop.r.effects()].concat(),
+            Value::Call(bind) => {
+                let mut effects: Vec<_> = bind.args.val.iter().flat_map(Value::effects).collect();
+                effects.push(Effect::Call(bind));
+                effects
+            }
+        }
+    }
+
+    /// Reads are done before the statement, so they can not depend on the left of `&&` or `||`.
+    pub fn check_reads(&self) -> Result<(), ReadErr> {
+        match self {
+            Value::Var(_, rest) => rest.iter().try_for_each(|idx| match idx {
+                Index::Value(idx) => idx.check_reads(),
+                Index::Attribute(_) => Ok(()),
+            }),
+            Value::Int(_) | Value::FuncPtr(_) => Ok(()),
+            Value::Neg(val) | Value::Not(val) => val.check_reads(),
+            Value::Extend(extend) => extend.val.check_reads(),
+            Value::BinOp(binop) => {
+                binop.l.check_reads()?;
+                binop.r.check_reads()
+            }
+            Value::Prop(prop) => {
+                if let PropOp::And | PropOp::Or = prop.op {
+                    let mut effects = prop.r.effects().into_iter();
+                    if let Some(read) = effects.find(|e| matches!(e, Effect::Read(_))) {
+                        let span = read.span();
+                        return Err(ReadErr::Conditional { span });
+                    }
+                }
+                prop.l.check_reads()?;
+                prop.r.check_reads()
+            }
+            Value::Call(bind) => bind.args.val.iter().try_for_each(Value::check_reads),
+        }
+    }
+
+    /// The first name or literal in the value.
+    fn span(&self) -> Option<SourceSpan> {
+        match self {
+            Value::Var(name, _) | Value::FuncPtr(name) => Some(name.span),
+            Value::Int(lit) => Some(lit.span),
+            Value::Neg(val) | Value::Not(val) => val.span(),
+            Value::BinOp(binop) => binop.l.span().or(binop.r.span()),
+            Value::Prop(prop) => prop.l.span().or(prop.r.span()),
+            Value::Call(bind) => Some(call_span(bind)),
+            Value::Extend(extend) => extend.val.span().or(Some(extend.size.span)),
+        }
+    }
+}
+
+/// Something that has to happen before a value can be converted.
+#[derive(Clone)]
+pub enum Effect<'a> {
+    Read(Read<'a>),
+    Call(&'a Bind),
+    FuncPtr(&'a Spanned<String>),
+}
+
+impl Effect<'_> {
+    /// The name that the result is bound to, it can not clash with variables.
+    pub fn key(&self) -> String {
+        match self {
+            Effect::Read(read) => read_key(read.name),
+            Effect::Call(bind) => {
+                let func = call_name(bind);
+                format!("{}()@{}", func.val, func.span.offset())
+            }
+            Effect::FuncPtr(name) => format!("&{}@{}", name.val, name.span.offset()),
+        }
+    }
+
+    pub fn span(&self) -> SourceSpan {
+        match self {
+            Effect::Read(read) => read.name.span,
+            Effect::Call(bind) => call_span(bind),
+            Effect::FuncPtr(name) => name.span,
+        }
+    }
+}
+
+fn call_span(bind: &Bind) -> SourceSpan {
+    call_name(bind).span
+}
+
+/// Calls in values always name their function, only a `let` can bind a bare tuple.
+pub(crate) fn call_name(bind: &Bind) -> &Spanned<String> {
+    bind.func.as_ref().unwrap()
+}
+
+/// An array read `base[idx]`, which reads the byte at `base + idx`.
+#[derive(Clone)]
+pub struct Read<'a> {
+    name: &'a Spanned<String>,
+    base: &'a [Index],
+    idx: &'a Value,
+}
+
+impl Read<'_> {
+    pub fn addr<T: Val>(
+        &self,
+        lookup: &HashMap<String, Nested<T>>,
+    ) -> Result<refinement::Free<T>, ConvertErr> {
+        if self.base.iter().any(|idx| matches!(idx, Index::Value(_))) {
+            let span = self.name.span;
+            return Err(ReadErr::Nested { span }.into());
+        }
+        let base = match Value::get(self.name, self.base, lookup)? {
+            Nested::Just(base) => refinement::Free::Var(base),
+            // data segments are constant addresses
+            Nested::Const(addr, size) => refinement::Free::Just(addr as i128, size),
+            Nested::Resource(..) => {
+                let span = self.name.span;
+                return Err(ScopeErr { span }.into());
+            }
+        };
+        let idx = self.idx.convert(lookup)?;
+        let (span, idx_span) = (Some(self.name.span), self.idx.span());
+        let (base, idx) = same_size((base, span), (idx, idx_span))?;
+        Ok(refinement::BinOp::Add.free(base, idx))
+    }
+}
+
+fn read_key(name: &Spanned<String>) -> String {
+    format!("{}[]@{}", name.val, name.span.offset())
 }
 
 impl BinOpValue {
-    pub fn convert<T: Clone>(
+    pub fn convert<T: Val>(
         &self,
         lookup: &HashMap<String, Nested<T>>,
-    ) -> Result<refinement::Free<T>, ScopeErr> {
+    ) -> Result<refinement::Free<T>, ConvertErr> {
         let op = match self.op {
             BinOp::Plus => refinement::BinOp::Add,
             BinOp::Minus => refinement::BinOp::Sub,
@@ -44,15 +261,113 @@ impl BinOpValue {
             BinOp::Divide => refinement::BinOp::Div,
             BinOp::Shl => refinement::BinOp::Shl,
             BinOp::Shr => refinement::BinOp::Shr,
+            BinOp::SModulo => refinement::BinOp::SRem,
+            BinOp::SDivide => refinement::BinOp::SDiv,
+            BinOp::SShr => refinement::BinOp::SShr,
+            BinOp::BitAnd => refinement::BinOp::BitAnd,
+            BinOp::BitOr => refinement::BinOp::BitOr,
+            BinOp::BitXor => refinement::BinOp::BitXor,
         };
-        Ok(refinement::Free::BinOp {
-            l: Rc::new(self.l.convert(lookup)?),
-            r: Rc::new(self.r.convert(lookup)?),
-            op,
+        let l = (self.l.convert(lookup)?, self.l.span());
+        let r = (self.r.convert(lookup)?, self.r.span());
+        let (l, r) = same_size(l, r)?;
+        Ok(op.free(l, r))
+    }
+}
+
+impl Extend {
+    pub fn convert<T: Val>(
+        &self,
+        lookup: &HashMap<String, Nested<T>>,
+    ) -> Result<refinement::Free<T>, ConvertErr> {
+        let val = self.val.convert(lookup)?;
+        let (have, need) = (val.size(), self.size.val);
+        if have > need {
+            let span = self.size.span;
+            return Err(WidthErr::Narrow { span, have, need }.into());
+        }
+        if have == need {
+            return Ok(val);
+        }
+        Ok(refinement::Free::Extend {
+            val: Rc::new(val),
+            size: need,
+            signed: self.signed,
         })
     }
 }
 
+/// Both operands need the same number of bits, literals take the size of the other operand.
+fn same_size<T: Val>(
+    (l, l_span): (Free<T>, Option<SourceSpan>),
+    (r, r_span): (Free<T>, Option<SourceSpan>),
+) -> Result<(Free<T>, Free<T>), WidthErr> {
+    let (l, r) = match (l.literal(), r.literal()) {
+        (true, false) => (l.fit(r.size(), l_span)?, r),
+        (false, true) => {
+            let size = l.size();
+            (l, r.fit(size, r_span)?)
+        }
+        (true, true) => {
+            let size = l.size().max(r.size());
+            (l.fit(size, l_span)?, r.fit(size, r_span)?)
+        }
+        (false, false) => (l, r),
+    };
+    if l.size() != r.size() {
+        return Err(WidthErr::Mixed {
+            l_span,
+            r_span,
+            l: l.size(),
+            r: r.size(),
+        });
+    }
+    Ok((l, r))
+}
+
+impl<T: Val> Free<T> {
+    /// Whether the value is calculated from literals only.
+    fn literal(&self) -> bool {
+        match self {
+            Free::Just(..) => true,
+            Free::BinOp { l, r, .. } => l.literal() && r.literal(),
+            Free::Var(_) | Free::Extend { .. } => false,
+        }
+    }
+
+    /// Change the size of the literals in the value, comparisons always have 32 bits.
+    /// Negative literals have to fit as signed values, others as unsigned values.
+    pub fn fit(self, size: u32, span: Option<SourceSpan>) -> Result<Self, WidthErr> {
+        if !self.literal() || self.size() == size {
+            return Ok(self);
+        }
+        let res = match self {
+            Free::Just(val, _) => {
+                if val < -(1 << (size - 1)) || val >= 1 << size {
+                    return Err(WidthErr::Literal { span, val, size });
+                }
+                Free::Just(val, size)
+            }
+            Free::BinOp { l, r, op } => op.free(
+                Rc::unwrap_or_clone(l).fit(size, span)?,
+                Rc::unwrap_or_clone(r).fit(size, span)?,
+            ),
+            free => free,
+        };
+        Ok(res)
+    }
+}
+
+/// Literals have 32 bits, unless they need more.
+fn literal<T>(val: i128) -> Free<T> {
+    let size = if (-(1 << 31)..1 << 32).contains(&val) {
+        32
+    } else {
+        64
+    };
+    Free::Just(val, size)
+}
+
 impl refinement::BinOp {
     pub fn free<T>(self, l: refinement::Free<T>, r: refinement::Free<T>) -> refinement::Free<T> {
         refinement::Free::BinOp {
@@ -63,17 +378,35 @@ impl refinement::BinOp {
     }
 }
 
+impl Match {
+    /// The condition for the arm with the pattern `lit`, it compares the value to the literal.
+    pub fn cond<T: Val>(
+        &self,
+        local: &Free<T>,
+        lit: u64,
+        span: SourceSpan,
+    ) -> Result<Free<T>, ConvertErr> {
+        let val = (local.clone(), self.val.span());
+        let lit = (literal(lit as i128), Some(span));
+        let (val, lit) = same_size(val, lit)?;
+        Ok(refinement::BinOp::Eq.free(val, lit))
+    }
+}
+
 impl Prop {
-    pub fn convert<T: Clone>(
+    pub fn convert<T: Val>(
         &self,
         lookup: &HashMap<String, Nested<T>>,
-    ) -> Result<refinement::Free<T>, ScopeErr> {
-        let l = self.l.convert(lookup)?;
-        let r = self.r.convert(lookup)?;
+    ) -> Result<refinement::Free<T>, ConvertErr> {
+        let l = (self.l.convert(lookup)?, self.l.span());
+        let r = (self.r.convert(lookup)?, self.r.span());
+        let (l, r) = same_size(l, r)?;
         use refinement::BinOp as Op;
         let res = match self.op {
             PropOp::Less => Op::Less.free(l, r),
             PropOp::LessEq => Op::LessEq.free(l, r),
+            PropOp::SLess => Op::SLess.free(l, r),
+            PropOp::SLessEq => Op::SLessEq.free(l, r),
             PropOp::Eq => Op::Eq.free(l, r),
             PropOp::NotEq => Op::NotEq.free(l, r),
             PropOp::And => Op::And.free(l, r),
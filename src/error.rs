@@ -1,39 +1,53 @@
-use std::{error::Error, fmt::Display, fs, process::exit};
+use std::{error::Error, fmt::Display};
 
 use miette::{Diagnostic, LabeledSpan, Report};
 
 use crate::refinement::builtin::builtins;
 
+#[derive(Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub code: String,
+}
+
 #[derive(Clone)]
 pub struct MultiFile {
     pub builtin: Vec<&'static str>,
-    pub code: String,
-    pub path: String,
+    // the first file is the main file, the others are imported
+    pub files: Vec<SourceFile>,
 }
 
 impl MultiFile {
-    pub fn new(path: &str) -> Self {
+    /// Read the main file and parse it together with all files that it imports.
+    pub fn new(path: &str) -> Result<Self, Report> {
+        let mut this = Self {
+            builtin: builtins(),
+            files: vec![],
+        };
+        this.load(path.to_owned(), None)?;
+        Ok(this)
+    }
+
+    /// A single main file with the given code, it can not import other files.
+    pub fn from_code(code: &str) -> Self {
         Self {
             builtin: builtins(),
-            code: fs::read_to_string(path).unwrap(),
-            path: path.to_owned(),
+            files: vec![SourceFile {
+                path: "main.lang".to_owned(),
+                code: code.to_owned(),
+            }],
         }
     }
 
-    pub fn offset(&self) -> usize {
-        self.builtin.iter().map(|x| x.len()).sum()
+    pub fn offset(&self, idx: usize) -> usize {
+        let builtin = self.builtin.iter().map(|x| x.len());
+        let files = self.files[..idx].iter().map(|x| x.code.len());
+        builtin.chain(files).sum()
     }
 
-    pub fn unwrap<T, E: Diagnostic + Send + Sync + 'static>(&self, res: Result<T, E>) -> T {
-        match res {
-            Ok(val) => val,
-            Err(e) => {
-                let report = Report::from(e);
-                let e = report.with_source_code(self.to_owned());
-                println!("{e:?}");
-                exit(1)
-            }
-        }
+    /// Attach the source code to the error, so that its labels can be shown.
+    pub fn report<E: Diagnostic + Send + Sync + 'static>(&self, err: E) -> Report {
+        Report::from(err).with_source_code(self.to_owned())
     }
 }
 
@@ -44,17 +58,19 @@ impl miette::SourceCode for MultiFile {
         context_lines_before: usize,
         context_lines_after: usize,
     ) -> Result<Box<dyn miette::SpanContents<'a> + 'a>, miette::MietteError> {
+        let builtin = self.builtin.iter().map(|code| ("builtin", *code));
+        let files = self.files.iter().map(|f| (&*f.path, &*f.code));
+        let mut all = builtin.chain(files).peekable();
+
         let mut start = 0;
-        let mut code = &*self.code;
-        let mut header = self.path.as_str();
-        for b in &self.builtin {
-            if span.offset() < start + b.len() {
-                code = b;
-                header = "builtin";
-                break;
+        let (header, code) = loop {
+            let (header, code) = all.next().unwrap();
+            // the end of the last file is also part of that file
+            if span.offset() < start + code.len() || all.peek().is_none() {
+                break (header, code);
             }
-            start += b.len();
-        }
+            start += code.len();
+        };
 
         let local_span = &(span.offset() - start, span.len()).into();
         let local = code.read_span(local_span, context_lines_before, context_lines_after)?;
@@ -1,11 +1,8 @@
-#![feature(extract_if)]
 #![feature(unique_rc_arc)]
 #![feature(never_type)]
 
 use std::{collections::HashMap, rc::Rc};
 
-use refinement::Free;
-
 // mod lpi;
 // mod selfref;
 // mod typ_check;
@@ -17,6 +14,7 @@ pub mod parse;
 pub mod refinement;
 mod solver;
 pub mod uninit_rc;
+pub mod wasm;
 // mod sequent;
 
 pub struct Struct<T> {
@@ -27,13 +25,16 @@ pub struct Struct<T> {
 #[derive(Clone)]
 pub struct FuncNested<T> {
     #[allow(clippy::complexity)]
-    val: Rc<dyn Fn(&[Free<T>]) -> Struct<T>>,
+    val: Rc<dyn Fn(&[T]) -> Struct<T>>,
 }
 
+/// A resource is stored with the values of all its fields.
 #[derive(Clone)]
 pub enum Nested<T> {
-    Resource(FuncNested<T>, Vec<Free<T>>),
+    Resource(FuncNested<T>, Vec<T>),
     Just(T),
+    // a constant with its number of bits, it does not have a value at runtime
+    Const(i64, u32),
 }
 
 impl<T> Nested<T> {
@@ -50,4 +51,15 @@ impl<T> Nested<T> {
             _ => panic!(),
         }
     }
+
+    pub fn flatten(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        match self {
+            Nested::Resource(_, args) => args.clone(),
+            Nested::Just(val) => vec![val.clone()],
+            Nested::Const(..) => vec![],
+        }
+    }
 }
@@ -1,28 +1,111 @@
 use lalrpop_util::{lalrpop_mod, ParseError};
-use miette::{Diagnostic, SourceSpan};
+use miette::{Diagnostic, IntoDiagnostic, Report, SourceSpan};
 use thiserror::Error;
 
-use crate::error::MultiFile;
+use std::{fs, path::Path};
+
+use crate::error::{MultiFile, SourceFile};
 
 use self::{
     code::ModuleParser,
-    expr::Module,
+    expr::{Module, Spanned},
     lexer::{Lexer, LexicalError, Token},
 };
 
 pub mod expr;
+pub mod format;
 pub mod lexer;
 pub mod types;
-lalrpop_mod!(pub code, "/parse/code.rs");
+lalrpop_mod!(
+    // the generated parser takes the recovered errors as a `&mut Vec`
+    // and has an empty line after one of its attributes
+    #[allow(clippy::ptr_arg, clippy::empty_line_after_outer_attr)]
+    pub code,
+    "/parse/code.rs"
+);
 
 impl MultiFile {
-    pub fn get_module(&self) -> Module {
-        let lexer = Lexer::new(&self.code, self.offset());
-        let parse = ModuleParser::new().parse(lexer);
-        self.unwrap(parse.map_err(ParseErr::from))
+    pub fn get_module(&self) -> Result<Module, Report> {
+        let modules = (0..self.files.len()).map(|idx| self.parse_file(idx));
+        let modules = modules.collect::<Result<Vec<_>, _>>()?;
+        Ok(Module(modules.into_iter().flat_map(|m| m.0).collect()))
+    }
+
+    fn parse_file(&self, idx: usize) -> Result<Module, Report> {
+        let lexer = Lexer::new(&self.files[idx].code, self.offset(idx));
+        let mut recovered = vec![];
+        let parse = ModuleParser::new().parse(&mut recovered, lexer);
+
+        let mut errors: Vec<_> = recovered.into_iter().map(|e| e.error.into()).collect();
+        match parse {
+            Ok(module) if errors.is_empty() => return Ok(module),
+            Ok(_) => {}
+            Err(e) => errors.push(e.into()),
+        }
+        if errors.len() == 1 {
+            Err(self.report(errors.remove(0)))
+        } else {
+            Err(self.report(ParseErrs { errors }))
+        }
+    }
+
+    /// The imports are found with the lexer, files are only parsed once in `get_module`.
+    fn imports(&self, idx: usize) -> Vec<Spanned<String>> {
+        let lexer = Lexer::new(&self.files[idx].code, self.offset(idx));
+        let tokens: Vec<_> = lexer.flatten().collect();
+        let imports = tokens.windows(2).filter_map(|pair| match pair {
+            [(_, Token::ImportKeyword, _), (l, Token::Str(path), r)] => Some(Spanned {
+                span: (*l, r - l).into(),
+                val: path.clone(),
+            }),
+            _ => None,
+        });
+        imports.collect()
+    }
+
+    /// Read a file and all files that it imports.
+    /// Every file is only read once, even when it is imported multiple times.
+    pub(crate) fn load(&mut self, path: String, import: Option<SourceSpan>) -> Result<(), Report> {
+        let canonical = fs::canonicalize(&path).ok();
+        if self
+            .files
+            .iter()
+            .any(|file| fs::canonicalize(&file.path).ok() == canonical)
+        {
+            return Ok(());
+        }
+
+        let read = fs::read_to_string(&path);
+        let code = match import {
+            Some(span) => read.map_err(|_| self.report(ImportErr { span }))?,
+            None => read.into_diagnostic()?,
+        };
+        self.files.push(SourceFile { path, code });
+
+        let idx = self.files.len() - 1;
+        for import in self.imports(idx) {
+            let dir = Path::new(&self.files[idx].path).parent().unwrap();
+            let path = dir.join(&import.val).to_string_lossy().into_owned();
+            self.load(path, Some(import.span))?;
+        }
+        Ok(())
     }
 }
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Can not read imported file")]
+pub struct ImportErr {
+    #[label = "The import"]
+    span: SourceSpan,
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Found {} syntax errors", errors.len())]
+pub struct ParseErrs {
+    #[related]
+    errors: Vec<ParseErr>,
+}
+
 #[derive(Debug, Diagnostic, Error)]
 pub enum ParseErr {
     #[error("Token is invalid")]
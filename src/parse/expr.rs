@@ -7,8 +7,12 @@ use super::types::{NamedConstraint, NegTyp, Prop};
 pub enum Def {
     Func(FuncDef),
     Typ(NamedConstraint),
+    Import(Spanned<String>),
+    Const(ConstDef),
+    Data(DataDef),
 }
 
+#[derive(Clone)]
 pub struct Spanned<T> {
     pub span: SourceSpan,
     pub val: T,
@@ -22,6 +26,33 @@ pub struct FuncDef {
     pub block: Rc<Spanned<Block>>,
 }
 
+/// A value that is known before the program runs.
+pub struct ConstDef {
+    pub name: Spanned<String>,
+    pub val: Value,
+}
+
+/// Bytes that are in memory before the program runs,
+/// the name is the address of the first byte.
+pub struct DataDef {
+    pub name: Spanned<String>,
+    pub data: Data,
+}
+
+pub enum Data {
+    Str(String),
+    Bytes(Vec<Spanned<u8>>),
+}
+
+impl Data {
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            Data::Str(text) => text.as_bytes().to_vec(),
+            Data::Bytes(bytes) => bytes.iter().map(|b| b.val).collect(),
+        }
+    }
+}
+
 pub struct Let {
     pub names: Vec<String>,
     pub bind: Bind,
@@ -39,9 +70,24 @@ pub enum Index {
 
 pub enum Value {
     Var(Spanned<String>, Vec<Index>),
-    Int32(i32),
+    // the number of bits depends on where the literal is used
+    Int(Spanned<u64>),
+    // negative literals are folded once their number of bits is known
+    Neg(Box<Value>),
+    Not(Box<Value>),
     BinOp(Box<BinOpValue>),
     Prop(Box<Prop>),
+    Call(Bind),
+    // pointer to a top-level function
+    FuncPtr(Spanned<String>),
+    Extend(Box<Extend>),
+}
+
+/// Zero or sign extension of an integer to a larger number of bits.
+pub struct Extend {
+    pub val: Value,
+    pub size: Spanned<u32>,
+    pub signed: bool,
 }
 
 pub struct BinOpValue {
@@ -58,11 +104,19 @@ pub enum BinOp {
     Divide,
     Shl,
     Shr,
+    SModulo,
+    SDivide,
+    SShr,
+    BitAnd,
+    BitOr,
+    BitXor,
 }
 
 pub enum Stmt {
     Let(Let),
+    Call(Bind),
     Debug,
+    Assert(Value),
     FuncDef(FuncDef),
     If(If),
 }
@@ -73,6 +127,8 @@ pub enum Block {
         next: Rc<Spanned<Block>>,
     },
     End(Bind),
+    IfElse(If, Rc<Spanned<Block>>),
+    Match(Match),
 }
 
 pub struct IfZero {
@@ -84,3 +140,21 @@ pub struct If {
     pub val: Value,
     pub block: Rc<Spanned<Block>>,
 }
+
+/// Arms match their literal in order, the last arm is `_` and matches the rest.
+/// Arms for 0, 1, 2, ... become a single jump table.
+pub struct Match {
+    pub val: Value,
+    pub arms: Vec<Arm>,
+}
+
+pub struct Arm {
+    pub pattern: Spanned<Pattern>,
+    pub block: Rc<Spanned<Block>>,
+}
+
+#[derive(PartialEq)]
+pub enum Pattern {
+    Int(u64),
+    Any,
+}
@@ -0,0 +1,474 @@
+//! Pretty printer that turns a parsed module back into canonical source.
+//!
+//! Literals are copied from the source with their spans, so they keep their spelling.
+//! The syntax tree does not contain comments, so they are recovered by lexing the source again.
+
+use std::{collections::VecDeque, rc::Rc};
+
+use logos::Logos;
+use miette::{Report, SourceSpan};
+
+use crate::error::MultiFile;
+
+use super::{
+    expr::{
+        BinOp, Bind, Block, Data, Def, FuncDef, If, Index, Match, Pattern, Spanned, Stmt, Value,
+    },
+    lexer::Token,
+    types::{Constraint, Param, ParamTyp, PosTyp, PropOp, ResourceTyp},
+};
+
+// precedence levels, higher binds stronger
+const OR: u8 = 1;
+const AND: u8 = 2;
+const COMPARE: u8 = 3;
+const BIT_OR: u8 = 4;
+const BIT_XOR: u8 = 5;
+const BIT_AND: u8 = 6;
+const SHIFT: u8 = 7;
+const SUM: u8 = 8;
+const PRODUCT: u8 = 9;
+const EXTEND: u8 = 10;
+const UNARY: u8 = 11;
+const ATOM: u8 = 12;
+
+impl BinOp {
+    fn syntax(&self) -> (&'static str, u8) {
+        match self {
+            BinOp::Plus => ("+", SUM),
+            BinOp::Minus => ("-", SUM),
+            BinOp::Times => ("*", PRODUCT),
+            BinOp::Modulo => ("%", PRODUCT),
+            BinOp::Divide => ("/", PRODUCT),
+            BinOp::SModulo => ("%$", PRODUCT),
+            BinOp::SDivide => ("/$", PRODUCT),
+            BinOp::Shl => ("<<", SHIFT),
+            BinOp::Shr => (">>", SHIFT),
+            BinOp::SShr => (">>$", SHIFT),
+            BinOp::BitAnd => ("&", BIT_AND),
+            BinOp::BitXor => ("^", BIT_XOR),
+            BinOp::BitOr => ("|", BIT_OR),
+        }
+    }
+}
+
+impl PropOp {
+    fn syntax(&self) -> (&'static str, u8) {
+        match self {
+            PropOp::Less => ("<", COMPARE),
+            PropOp::LessEq => ("<=", COMPARE),
+            PropOp::SLess => ("<$", COMPARE),
+            PropOp::SLessEq => ("<=$", COMPARE),
+            PropOp::Eq => ("==", COMPARE),
+            PropOp::NotEq => ("!=", COMPARE),
+            PropOp::MulSafe => ("*?", COMPARE),
+            PropOp::AddSafe => ("+?", COMPARE),
+            PropOp::And => ("&&", AND),
+            PropOp::Or => ("||", OR),
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            '\\' | '"' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Param {
+    fn syntax(&self) -> String {
+        match &self.typ {
+            ParamTyp::Int(32) => self.name.clone(),
+            ParamTyp::Int(size) => format!("{}: u{size}", self.name),
+            ParamTyp::Custom(typ) => format!("{}: {}", self.name, typ.syntax()),
+        }
+    }
+}
+
+impl ResourceTyp {
+    fn syntax(&self) -> String {
+        if self.args.is_empty() {
+            return self.name.val.clone();
+        }
+        let args: Vec<_> = self.args.iter().map(ResourceTyp::syntax).collect();
+        // `>>` is a single token, so nested arguments are closed with `> >`
+        let args = args.join(", ");
+        let space = if args.ends_with('>') { " " } else { "" };
+        format!("{}<{args}{space}>", self.name.val)
+    }
+}
+
+struct Printer<'a> {
+    code: &'a str,
+    offset: usize,
+    // comments in source order, with local positions
+    comments: VecDeque<(usize, &'a str)>,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(code: &'a str, offset: usize) -> Self {
+        let mut comments = VecDeque::new();
+        for (token, span) in Token::lexer(code).spanned() {
+            if let Ok(Token::Comment(_)) = token {
+                comments.push_back((span.start, code[span].trim_end()));
+            }
+        }
+        Self {
+            code,
+            offset,
+            comments,
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn open(&mut self, text: &str) {
+        self.line(text);
+        self.indent += 1;
+    }
+
+    /// Empty line, but never at the start of the output or of a block.
+    fn blank(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether there is an empty line in the source right before `pos`.
+    fn gap(&self, pos: usize) -> bool {
+        let before = self.code[..pos].trim_end();
+        self.code[before.len()..pos].matches('\n').count() > 1
+    }
+
+    /// Write all comments that come before `pos`.
+    /// Comments that follow code on the same line stay behind that code.
+    fn comments(&mut self, pos: usize, mut separate: bool) {
+        while let Some(&(start, text)) = self.comments.front().filter(|c| c.0 < pos) {
+            self.comments.pop_front();
+            let line_start = self.code[..start].rfind('\n').map_or(0, |i| i + 1);
+            if !self.code[line_start..start].trim().is_empty() {
+                let end = self.out.trim_end().len();
+                self.out.truncate(end);
+                self.out.push(' ');
+                self.out.push_str(text);
+                self.out.push('\n');
+                continue;
+            }
+            if separate || self.gap(start) {
+                self.blank();
+            }
+            separate = false;
+            self.line(text);
+        }
+        if separate {
+            self.blank();
+        }
+    }
+
+    /// Prepare for an item that starts at the global position `pos`.
+    fn leading(&mut self, pos: usize, separate: bool) {
+        let pos = pos - self.offset;
+        self.comments(pos, separate);
+        if self.gap(pos) {
+            self.blank();
+        }
+    }
+
+    /// Finish a block whose last item ends at the global position `end`.
+    /// Comments before the closing brace are still part of the block.
+    fn dedent(&mut self, end: usize) {
+        let mut pos = end - self.offset;
+        loop {
+            let rest = &self.code[pos..];
+            let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+            pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                break;
+            }
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+        self.comments(pos, false);
+        self.indent -= 1;
+    }
+
+    /// The source text of a literal, so it keeps its spelling.
+    fn text(&self, span: SourceSpan) -> &'a str {
+        &self.code[span.offset() - self.offset..][..span.len()]
+    }
+
+    fn operand(&mut self, val: &Value, min: u8) -> String {
+        let (text, prec) = self.value(val);
+        if prec < min {
+            format!("({text})")
+        } else {
+            text
+        }
+    }
+
+    fn binary(&mut self, l: &Value, op: (&str, u8), r: &Value) -> (String, u8) {
+        let (op, prec) = op;
+        // comparisons do not chain
+        let left = if prec == COMPARE { prec + 1 } else { prec };
+        let l = self.operand(l, left);
+        let r = self.operand(r, prec + 1);
+        (format!("{l} {op} {r}"), prec)
+    }
+
+    fn value(&mut self, val: &Value) -> (String, u8) {
+        match val {
+            Value::Var(name, indices) => {
+                let mut text = name.val.clone();
+                for index in indices {
+                    match index {
+                        Index::Attribute(attr) => text += &format!(".{}", attr.val),
+                        Index::Value(idx) => text += &format!("[{}]", self.value(idx).0),
+                    }
+                }
+                (text, ATOM)
+            }
+            Value::Int(lit) => (self.text(lit.span).to_owned(), ATOM),
+            Value::Neg(val) => (format!("-{}", self.operand(val, UNARY)), UNARY),
+            Value::Not(val) => (format!("~{}", self.operand(val, UNARY)), UNARY),
+            Value::BinOp(bin) => self.binary(&bin.l, bin.op.syntax(), &bin.r),
+            Value::Prop(prop) => self.binary(&prop.l, prop.op.syntax(), &prop.r),
+            Value::Call(bind) => (self.bind(bind), ATOM),
+            Value::FuncPtr(name) => (format!("&{}", name.val), ATOM),
+            Value::Extend(extend) => {
+                let val = self.operand(&extend.val, EXTEND);
+                let op = if extend.signed { "as$" } else { "as" };
+                (format!("{val} {op} u{}", extend.size.val), EXTEND)
+            }
+        }
+    }
+
+    fn values(&mut self, vals: &[Value]) -> String {
+        let vals: Vec<_> = vals.iter().map(|val| self.value(val).0).collect();
+        vals.join(", ")
+    }
+
+    fn bind(&mut self, bind: &Bind) -> String {
+        let func = bind.func.as_ref().map_or("", |func| &func.val);
+        format!("{func}({})", self.values(&bind.args.val))
+    }
+
+    fn constraint(&mut self, constraint: &Constraint) -> String {
+        match constraint {
+            Constraint::Forall(forall) => format!(
+                "{}{} for ({}) if {}",
+                forall.access.syntax(),
+                forall.named.syntax(),
+                forall.names.join(", "),
+                self.value(&forall.cond).0
+            ),
+            Constraint::Switch(name, switch) => {
+                let name = name
+                    .as_ref()
+                    .map_or(String::new(), |name| format!("{name} = "));
+                let access = switch.access.syntax();
+                let args = self.values(&switch.args);
+                let mut text = format!("{name}{access}{}({args})", switch.named.syntax());
+                if let Some(cond) = &switch.cond {
+                    text += &format!(" if {}", self.value(cond).0);
+                }
+                text
+            }
+            Constraint::Assert(val) => format!("assert {}", self.value(val).0),
+            Constraint::Let(name, val) => format!("let {name} = {}", self.value(val).0),
+            Constraint::Exactly(name) => name.val.clone(),
+            Constraint::Func(..) => unreachable!("contracts are printed by `pos_typ`"),
+        }
+    }
+
+    /// Continue `head` with the type, returns the text that ends the type.
+    fn pos_typ(&mut self, head: String, typ: &Spanned<PosTyp>) -> String {
+        let params: Vec<_> = typ.val.params.iter().map(Param::syntax).collect();
+        let head = format!("{head}({})", params.join(", "));
+        let Some(last) = typ.val.parts.last() else {
+            return head;
+        };
+        self.open(&format!("{head} where {{"));
+        for part in &typ.val.parts {
+            self.leading(part.span.offset(), false);
+            if let Constraint::Func(name, typ) = &part.val {
+                let args = self.pos_typ(format!("fn {}", name.val), &typ.args);
+                let end = self.pos_typ(format!("{args} -> "), &typ.ret);
+                self.line(&format!("{end};"));
+                continue;
+            }
+            let text = self.constraint(&part.val);
+            self.line(&format!("{text};"));
+        }
+        self.dedent(last.span.offset() + last.span.len());
+        "}".to_owned()
+    }
+
+    fn func(&mut self, func: &FuncDef, end: &str) {
+        let typ = &func.typ;
+        let head = if Rc::ptr_eq(&typ.args, &typ.ret) {
+            self.pos_typ(format!("loop {}", func.name), &typ.args)
+        } else {
+            let args = self.pos_typ(format!("fn {}", func.name), &typ.args);
+            self.pos_typ(format!("{args} -> "), &typ.ret)
+        };
+        self.open(&format!("{head} = {{"));
+        self.block(&func.block);
+        self.line(end);
+    }
+
+    fn block(&mut self, block: &Spanned<Block>) {
+        let mut current = block;
+        loop {
+            match &current.val {
+                Block::Stmt { step, next } => {
+                    self.leading(step.span.offset(), false);
+                    self.stmt(&step.val);
+                    current = next;
+                }
+                Block::End(bind) => {
+                    self.leading(current.span.offset(), false);
+                    let bind = self.bind(bind);
+                    self.line(&format!("return {bind}"));
+                    break;
+                }
+                Block::IfElse(cond, other) => {
+                    self.leading(current.span.offset(), false);
+                    self.if_else("", cond, other);
+                    break;
+                }
+                Block::Match(Match { val, arms }) => {
+                    self.leading(current.span.offset(), false);
+                    let val = self.value(val).0;
+                    self.open(&format!("match {val} {{"));
+                    for arm in arms {
+                        self.leading(arm.pattern.span.offset(), false);
+                        let pattern = match arm.pattern.val {
+                            Pattern::Int(_) => self.text(arm.pattern.span).to_owned(),
+                            Pattern::Any => "_".to_owned(),
+                        };
+                        self.open(&format!("{pattern} => {{"));
+                        self.block(&arm.block);
+                        self.line("},");
+                    }
+                    self.indent -= 1;
+                    self.line("}");
+                    break;
+                }
+            }
+        }
+        self.dedent(block.span.offset() + block.span.len());
+    }
+
+    /// An `else` that only contains another `if` is written as `else if`.
+    fn if_else(&mut self, head: &str, cond: &If, other: &Spanned<Block>) {
+        let val = self.value(&cond.val).0;
+        self.open(&format!("{head}if {val} {{"));
+        self.block(&cond.block);
+        match &other.val {
+            Block::IfElse(cond, other) => self.if_else("} else ", cond, other),
+            _ => {
+                self.open("} else {");
+                self.block(other);
+                self.line("}");
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(lets) => {
+                let bind = self.bind(&lets.bind);
+                self.line(&format!("let ({}) = {bind};", lets.names.join(", ")));
+            }
+            Stmt::Call(bind) => {
+                let bind = self.bind(bind);
+                self.line(&format!("{bind};"));
+            }
+            Stmt::Debug => self.line("#debug;"),
+            Stmt::Assert(val) => {
+                let val = self.value(val).0;
+                self.line(&format!("assert {val};"));
+            }
+            Stmt::FuncDef(func) => self.func(func, "};"),
+            Stmt::If(cond) => {
+                let val = self.value(&cond.val).0;
+                self.open(&format!("if {val} {{"));
+                self.block(&cond.block);
+                self.line("};");
+            }
+        }
+    }
+
+    fn def(&mut self, def: &Def) {
+        match def {
+            Def::Func(func) => {
+                self.leading(func.typ.args.span.offset(), true);
+                self.func(func, "}");
+            }
+            Def::Typ(named) => {
+                self.leading(named.typ.span.offset(), true);
+                let mut head = format!("type {}", named.name);
+                if !named.generics.is_empty() {
+                    head += &format!("<{}>", named.generics.join(", "));
+                }
+                let end = self.pos_typ(head, &named.typ);
+                self.line(&end);
+            }
+            Def::Import(path) => {
+                self.leading(path.span.offset(), true);
+                self.line(&format!("import {};", quote(&path.val)));
+            }
+            Def::Const(def) => {
+                self.leading(def.name.span.offset(), true);
+                let val = self.value(&def.val).0;
+                self.line(&format!("const {} = {val};", def.name.val));
+            }
+            Def::Data(def) => {
+                self.leading(def.name.span.offset(), true);
+                let data = match &def.data {
+                    Data::Str(text) => quote(text),
+                    Data::Bytes(bytes) => {
+                        let bytes: Vec<_> = bytes.iter().map(|b| self.text(b.span)).collect();
+                        format!("[{}]", bytes.join(", "))
+                    }
+                };
+                self.line(&format!("data {} = {data};", def.name.val));
+            }
+        }
+    }
+}
+
+impl MultiFile {
+    /// Format the main file, imported files are left alone.
+    pub fn format(&self) -> Result<String, Report> {
+        let module = self.parse_file(0)?;
+        let mut printer = Printer::new(&self.files[0].code, self.offset(0));
+        for def in &module.0 {
+            printer.def(def);
+        }
+        printer.comments(printer.code.len(), false);
+        Ok(printer.out)
+    }
+}
@@ -3,20 +3,28 @@ use miette::{Diagnostic, SourceSpan};
 
 #[derive(Clone, Logos, Debug, PartialEq)]
 #[logos(skip r"[ \t\n\f]+")]
-#[logos(skip r"//.*")]
 pub enum Token {
+    // comments are only kept for the formatter, the parser never sees them
+    #[regex(r"//.*", |lex| lex.slice().to_owned())]
+    Comment(String),
     #[regex("[@]?[_a-zA-Z][_a-zA-Z0-9]*", |lex| lex.slice().to_owned())]
     Var(String),
-    #[regex(r"\d+", |lex| lex.slice().parse::<i32>().unwrap())]
-    #[regex(r"'\d'", |lex| lex.slice().as_bytes()[1] as i32)]
-    #[regex(r"'\\n'", |_lex| 10)]
-    Num(i32),
+    #[regex(r"\d+", |lex| radix(lex.slice(), 10))]
+    #[regex(r"0x[0-9a-fA-F]+", |lex| radix(&lex.slice()[2..], 16))]
+    #[regex(r"0b[01]+", |lex| radix(&lex.slice()[2..], 2))]
+    #[regex(r"'[ -&(-\[\]-~]'", |lex| lex.slice().as_bytes()[1] as u64)]
+    #[regex(r#"'\\[nrt0\\'"]'"#, |lex| escape(lex.slice().as_bytes()[2]).map(u64::from))]
+    Num(u64),
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| unescape(lex.slice()))]
+    Str(String),
     #[token("=")]
     Assign,
     #[token(":")]
     Colon,
     #[token("->")]
     Arrow,
+    #[token("=>")]
+    FatArrow,
     #[token(".")]
     Period,
     #[token(",")]
@@ -41,6 +49,18 @@ pub enum Token {
     Less,
     #[token("<=")]
     LessEq,
+    #[token(">")]
+    Greater,
+    #[token("%$")]
+    SMod,
+    #[token("/$")]
+    SDiv,
+    #[token(">>$")]
+    SShr,
+    #[token("<$")]
+    SLess,
+    #[token("<=$")]
+    SLessEq,
     #[token("==")]
     Eq,
     #[token("!=")]
@@ -49,6 +69,14 @@ pub enum Token {
     And,
     #[token("||")]
     Or,
+    #[token("&")]
+    BitAnd,
+    #[token("|")]
+    BitOr,
+    #[token("^")]
+    BitXor,
+    #[token("~")]
+    BitNot,
     #[token("*?")]
     MulSafe,
     #[token("+?")]
@@ -71,6 +99,10 @@ pub enum Token {
     LoopKeyword,
     #[token("if")]
     IfKeyword,
+    #[token("else")]
+    ElseKeyword,
+    #[token("match")]
+    MatchKeyword,
     #[token("for")]
     ForKeyword,
     #[token("type")]
@@ -81,12 +113,55 @@ pub enum Token {
     AssertKeyword,
     #[token("let")]
     LetKeyword,
+    #[token("move")]
+    MoveKeyword,
+    #[token("inspect")]
+    InspectKeyword,
     #[token("return")]
     ReturnKeyword,
+    #[token("import")]
+    ImportKeyword,
+    #[token("const")]
+    ConstKeyword,
+    #[token("data")]
+    DataKeyword,
+    #[token("as")]
+    AsKeyword,
+    #[token("as$")]
+    SAsKeyword,
     #[token("#debug")]
     DebugKeyword,
 }
 
+/// the number of bits of a literal is only known where it is used
+fn radix(digits: &str, radix: u32) -> Option<u64> {
+    u64::from_str_radix(digits, radix).ok()
+}
+
+fn escape(c: u8) -> Option<u8> {
+    let c = match c {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'0' => b'\0',
+        b'\\' | b'\'' | b'"' => c,
+        _ => return None,
+    };
+    Some(c)
+}
+
+fn unescape(lit: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(escape(u8::try_from(chars.next()?).ok()?)? as char),
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
 #[derive(Debug, Diagnostic, thiserror::Error)]
 #[error("token is invalid")]
 pub struct LexicalError {
@@ -112,7 +187,9 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Result<(usize, Token, usize), LexicalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.token_stream.next().map(|(token, span)| match token {
+        let tokens = self.token_stream.by_ref();
+        let mut tokens = tokens.filter(|(token, _)| !matches!(token, Ok(Token::Comment(_))));
+        tokens.next().map(|(token, span)| match token {
             Err(()) => Err(LexicalError {
                 span: (self.offset + span.start, span.end - span.start).into(),
             }),
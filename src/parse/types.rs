@@ -7,25 +7,56 @@ use super::expr::{Spanned, Value};
 #[derive(Clone)]
 pub struct NamedConstraint {
     pub name: String,
+    // other resource types that the named type is parameterised over
+    pub generics: Vec<String>,
     pub typ: Rc<Spanned<PosTyp>>,
 }
 
+/// A resource type, named types can take other resource types as arguments.
+#[derive(Clone)]
+pub struct ResourceTyp {
+    pub name: Spanned<String>,
+    pub args: Vec<ResourceTyp>,
+}
+
 #[derive(Clone)]
 pub enum ParamTyp {
-    I32,
-    Custom {
-        name: String,
-        // args: Vec<ParamTypArg>,
-    },
+    // an integer with the number of bits, parameters without a type have 32 bits
+    Int(u32),
+    Custom(ResourceTyp),
+}
+
+impl ParamTyp {
+    /// The integer types are written like resource types without arguments.
+    pub fn new(typ: ResourceTyp) -> Self {
+        match int_size(&typ.name.val) {
+            Some(size) if typ.args.is_empty() => ParamTyp::Int(size),
+            _ => ParamTyp::Custom(typ),
+        }
+    }
+}
+
+/// The number of bits of an integer type like `u8`.
+pub fn int_size(name: &str) -> Option<u32> {
+    match name {
+        "u8" => Some(8),
+        "u16" => Some(16),
+        "u32" => Some(32),
+        "u64" => Some(64),
+        _ => None,
+    }
 }
 
-// pub enum ParamTypArg {
-//     Bind(Option<String>),
-//     Exactly(Value),
-// }
+/// A parameter with a custom type is bound to the resource of that type,
+/// its fields are the parameters of the named type.
+#[derive(Clone)]
+pub struct Param {
+    pub name: String,
+    pub typ: ParamTyp,
+}
 
 pub struct PosTyp {
-    pub names: Vec<String>,
+    pub params: Vec<Param>,
     pub parts: Vec<Spanned<Constraint>>,
 }
 
@@ -38,27 +69,55 @@ pub struct NegTyp {
 pub enum Constraint {
     Forall(Forall),
     Switch(Option<String>, Switch),
-    Assert(Prop),
+    Assert(Value),
     Let(String, Value),
-    // Func(Term, NegTyp),
+    // the variable points to a function with this contract
+    Func(Spanned<String>, NegTyp),
     Exactly(Spanned<String>),
 }
 
+/// Resources are moved to the new owner, unless they are only inspected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Implicit,
+    Move,
+    Inspect,
+}
+
+impl Access {
+    pub fn moved(self) -> bool {
+        self != Access::Inspect
+    }
+
+    /// The keyword as it was written in the source.
+    pub fn syntax(self) -> &'static str {
+        match self {
+            Access::Implicit => "",
+            Access::Move => "move ",
+            Access::Inspect => "inspect ",
+        }
+    }
+}
+
 pub struct Forall {
-    pub named: Spanned<String>,
+    pub access: Access,
+    pub named: ResourceTyp,
     pub names: Vec<String>,
-    pub cond: Rc<Prop>,
+    pub cond: Rc<Value>,
 }
 
 pub struct Switch {
+    pub access: Access,
     pub cond: Option<Value>,
-    pub named: Spanned<String>,
+    pub named: ResourceTyp,
     pub args: Vec<Value>,
 }
 
 pub enum PropOp {
     Less,
     LessEq,
+    SLess,
+    SLessEq,
     Eq,
     NotEq,
     And,
@@ -16,7 +16,7 @@ mod verify;
 
 use miette::{Diagnostic, SourceSpan};
 
-use crate::desugar::Desugar;
+use crate::desugar::{ConvertErr, Desugar};
 use crate::{parse, Nested};
 
 use self::func_term::FuncTerm;
@@ -34,8 +34,26 @@ pub enum Free<T> {
         r: Rc<Free<T>>,
         op: BinOp,
     },
-    Just(i64, u32),
+    // literals can be negative or larger than `i64::MAX`, only the lowest bits are used
+    Just(i128, u32),
     Var(T),
+    // zero or sign extension to the number of bits
+    Extend {
+        val: Rc<Free<T>>,
+        size: u32,
+        signed: bool,
+    },
+}
+
+impl<T: Val> Free<T> {
+    /// The number of bits, the operands of a binary operation have the same size.
+    pub fn size(&self) -> u32 {
+        match self {
+            Free::BinOp { l, op, .. } => op.size(l.size()),
+            Free::Just(_, size) | Free::Extend { size, .. } => *size,
+            Free::Var(var) => var.size(),
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -55,6 +73,33 @@ pub enum BinOp {
     AddSafe,
     Shl,
     Shr,
+    SDiv,
+    SRem,
+    SLess,
+    SLessEq,
+    SShr,
+    BitAnd,
+    BitOr,
+    BitXor,
+}
+
+impl BinOp {
+    /// Comparisons have 32 bits, other operations the size of their operands.
+    pub fn size(&self, operands: u32) -> u32 {
+        match self {
+            BinOp::Eq
+            | BinOp::Less
+            | BinOp::LessEq
+            | BinOp::NotEq
+            | BinOp::And
+            | BinOp::Or
+            | BinOp::MulSafe
+            | BinOp::AddSafe
+            | BinOp::SLess
+            | BinOp::SLessEq => 32,
+            _ => operands,
+        }
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -71,6 +116,7 @@ pub struct Cond {
 #[derive(Clone)]
 pub struct Switch {
     pub resource: Resource,
+    pub moved: bool,
     pub args: Vec<Term>,
     pub cond: Term,
     pub span: Option<SourceSpan>,
@@ -95,6 +141,8 @@ pub struct Forall {
 pub struct CtxForall {
     pub have: Forall,
     pub value: FuncTerm,
+    // borrowed resources can be inspected, but they can not be moved or leaked
+    pub borrowed: bool,
 }
 
 impl Debug for CtxForall {
@@ -112,7 +160,11 @@ pub struct SubContext {
     forall: Vec<CtxForall>,
     // these do not have to exist, but might
     hints: Vec<Hint>,
+    // function pointers with their known contracts
+    funcs: Vec<(Term, Fun<NegTyp>)>,
     scope: Option<HashMap<String, Nested<Term>>>,
+    // resources that are inspected by the current call, they are checked again after it
+    inspected: Vec<Forall>,
 }
 
 #[derive(Clone)]
@@ -186,15 +238,18 @@ pub struct Spanned<T: ?Sized> {
 }
 
 impl<V: Val> Lambda<V> {
-    pub fn inst(&self, var: &[V]) -> Spanned<Expr<V>> {
+    pub fn inst(&self, var: &[V]) -> Inst<V> {
         (self.func)(var)
     }
 }
 
+/// The body of a lambda is converted when it is instantiated, this can find errors in the program.
+pub type Inst<V> = Result<Spanned<Expr<V>>, ConvertErr>;
+
 #[allow(clippy::type_complexity)]
-pub struct Lambda<V: Val, F = dyn Fn(&[V]) -> Spanned<Expr<V>>>
+pub struct Lambda<V: Val, F = dyn Fn(&[V]) -> Inst<V>>
 where
-    F: ?Sized + Fn(&[V]) -> Spanned<Expr<V>>,
+    F: ?Sized + Fn(&[V]) -> Inst<V>,
 {
     pub _val: PhantomData<V>,
     pub func: F,
@@ -225,6 +280,13 @@ pub struct FuncName<V: Val> {
 pub enum Thunk<V: Val> {
     Local(V::Func),
     Builtin(Builtin),
+    /// call through a function pointer, the sizes of the arguments and
+    /// return values are known from its contract
+    Indirect {
+        ptr: Free<V>,
+        args: Vec<u32>,
+        rets: Vec<u32>,
+    },
 }
 
 /// Named resource name
@@ -242,11 +304,14 @@ impl PartialEq for Name {
 
 pub trait Val: Clone + Sized + 'static {
     type Func: Clone;
+    /// The number of bits of the integer.
+    fn size(&self) -> u32;
     fn make(
         desugar: &Desugar<Self>,
+        name: &str,
         lamb: &Weak<Lambda<Self>>,
         typ: &parse::types::NegTyp,
-    ) -> Self::Func;
+    ) -> Result<Self::Func, ConvertErr>;
 }
 
 impl<T> From<&Spanned<T>> for SourceSpan {
@@ -292,6 +357,9 @@ pub enum Expr<V: Val> {
     /// loop back to an assigment
     Loop(V::Func, Value<V>),
 
+    /// check a proposition and assume it in the rest
+    Assert(Value<V>, Box<Spanned<Expr<V>>>),
+
     Debug(Box<Spanned<Expr<V>>>),
 }
 
@@ -303,4 +371,4 @@ pub enum Expr<V: Val> {
 // - Flatten types
 // - No longer return constraints, verified eagerly now
 // - Add support for mutable memory using resources
-// - model functions as pointers
+// - Model functions as pointers
@@ -1,6 +1,14 @@
+use std::rc::Rc;
+
+use indenter::indented;
+use miette::SourceSpan;
+
 use crate::desugar;
 
-use super::{term::Term, BinOp, Free, Fun, NegTyp, SubContext};
+use super::{
+    eval::Int, heap::ConsumeErr, term::Term, verify::format_model, BinOp, Free, Fun, NegTyp,
+    PosTyp, SubContext,
+};
 
 pub enum Builtin {
     Read8,
@@ -8,18 +16,28 @@ pub enum Builtin {
     Write8,
     Write32,
     Pack(Fun<NegTyp>),
-    Alloc,
+    /// allocations start after the data segments, which end at this address
+    Alloc(u32),
+    /// pointer to the function at the index of the table, with its contract
+    FuncPtr(usize, Fun<NegTyp>),
 }
 
 impl SubContext {
-    pub fn check_binop(&self, op: &BinOp, _l: &Term, r: &Term) {
+    /// Divisions are only allowed when they can not fail.
+    pub fn check_binop(
+        &self,
+        op: &BinOp,
+        l: &Term,
+        r: &Term,
+        span: Option<SourceSpan>,
+    ) -> Result<(), ConsumeErr> {
         // TODO: check int sizes here?
         match op {
             BinOp::Add => {}
             BinOp::Sub => {}
-            BinOp::Div => self.assume.verify_prop(&r.not_zero()).unwrap(),
+            BinOp::Div => self.check_divisor(r, span)?,
             BinOp::Mul => {}
-            BinOp::Rem => self.assume.verify_prop(&r.not_zero()).unwrap(),
+            BinOp::Rem => self.check_divisor(r, span)?,
             BinOp::Eq => {}
             BinOp::Less => {}
             BinOp::And => {}
@@ -30,8 +48,52 @@ impl SubContext {
             BinOp::AddSafe => {}
             BinOp::Shl => {}
             BinOp::Shr => {}
+            BinOp::SDiv => {
+                self.check_divisor(r, span)?;
+                // the only signed division that overflows
+                let min = Term::nat(i64::MIN >> (64 - l.get_size()), l.get_size());
+                let overflow = l.eq(&min).bool_and(&r.eq(&Term::nat(-1, r.get_size())));
+                if let Some(example) = self.example(&overflow.is_zero()) {
+                    return Err(ConsumeErr::DivOverflow {
+                        value: span,
+                        help: format!(
+                            "Here is a valid example for which \nit overflows: \n{example}"
+                        ),
+                    });
+                }
+            }
+            BinOp::SRem => self.check_divisor(r, span)?,
+            BinOp::SLess => {}
+            BinOp::SLessEq => {}
+            BinOp::SShr => {}
+            BinOp::BitAnd => {}
+            BinOp::BitOr => {}
+            BinOp::BitXor => {}
+        }
+        Ok(())
+    }
+
+    fn check_divisor(&self, r: &Term, span: Option<SourceSpan>) -> Result<(), ConsumeErr> {
+        match self.example(&r.not_zero()) {
+            Some(example) => Err(ConsumeErr::DivZero {
+                value: span,
+                help: format!(
+                    "Here is a valid example for which \nthe divisor is zero: \n{example}"
+                ),
+            }),
+            None => Ok(()),
         }
     }
+
+    /// The variables in scope for which the proposition is false, if there are any.
+    fn example(&self, prop: &Term) -> Option<String> {
+        let model = self.assume.verify_prop(prop).err()?;
+        let mut out = String::new();
+        if let Some(scope) = &self.scope {
+            format_model(indented(&mut out), model, scope);
+        }
+        Some(out)
+    }
 }
 
 impl BinOp {
@@ -52,29 +114,55 @@ impl BinOp {
             BinOp::AddSafe => l.uadd_no_overlow(r),
             BinOp::Shl => l.shl(r),
             BinOp::Shr => l.shr(r),
+            BinOp::SDiv => l.sdiv(r),
+            BinOp::SRem => l.srem(r),
+            BinOp::SLess => l.slt(r),
+            BinOp::SLessEq => l.sle(r),
+            BinOp::SShr => l.ashr(r),
+            BinOp::BitAnd => l.bit_and(r),
+            BinOp::BitOr => l.bit_or(r),
+            BinOp::BitXor => l.bit_xor(r),
         }
     }
 
-    pub fn eval(&self, l: i32, r: i32) -> i32 {
-        let (l, r) = (l as u32, r as u32);
+    /// There is no result when dividing by zero.
+    pub fn eval(&self, l: Int, r: Int) -> Option<Int> {
+        let (size, max) = (l.size, Int::new(-1, l.size).val);
+        let (sl, sr) = (l.signed(), r.signed());
+        let (l, r) = (l.val, r.val);
+        let divides = matches!(self, BinOp::Div | BinOp::Rem | BinOp::SDiv | BinOp::SRem);
+        if divides && r == 0 {
+            return None;
+        }
         let res = match self {
-            BinOp::Add => l + r,
-            BinOp::Sub => l - r,
+            BinOp::Add => l.wrapping_add(r),
+            BinOp::Sub => l.wrapping_sub(r),
             BinOp::Div => l / r,
-            BinOp::Mul => l * r,
+            BinOp::Mul => l.wrapping_mul(r),
             BinOp::Rem => l % r,
-            BinOp::Eq => (l == r) as u32,
-            BinOp::Less => (l < r) as u32,
-            BinOp::And => l & r,
-            BinOp::Or => l | r,
-            BinOp::LessEq => (l <= r) as u32,
-            BinOp::NotEq => (l != r) as u32,
-            BinOp::MulSafe => !l.overflowing_mul(r).1 as u32,
-            BinOp::AddSafe => !l.overflowing_add(r).1 as u32,
+            BinOp::Eq => (l == r) as u64,
+            BinOp::Less => (l < r) as u64,
+            BinOp::And => (l != 0 && r != 0) as u64,
+            BinOp::Or => (l != 0 || r != 0) as u64,
+            BinOp::LessEq => (l <= r) as u64,
+            BinOp::NotEq => (l != r) as u64,
+            BinOp::MulSafe => l.checked_mul(r).is_some_and(|res| res <= max) as u64,
+            BinOp::AddSafe => l.checked_add(r).is_some_and(|res| res <= max) as u64,
+            // shifting out all bits gives zero, like in the solver
+            BinOp::Shl if r >= size as u64 => 0,
             BinOp::Shl => l << r,
+            BinOp::Shr if r >= size as u64 => 0,
             BinOp::Shr => l >> r,
+            BinOp::SDiv => sl.wrapping_div(sr) as u64,
+            BinOp::SRem => sl.wrapping_rem(sr) as u64,
+            BinOp::SLess => (sl < sr) as u64,
+            BinOp::SLessEq => (sl <= sr) as u64,
+            BinOp::SShr => (sl >> r.min(63)) as u64,
+            BinOp::BitAnd => l & r,
+            BinOp::BitOr => l | r,
+            BinOp::BitXor => l ^ r,
         };
-        res as i32
+        Some(Int::new(res as i64, self.size(size)))
     }
 }
 
@@ -82,8 +170,9 @@ impl Free<Term> {
     pub fn make_term(&self) -> Term {
         match self {
             Free::BinOp { l, r, op } => op.apply(&l.make_term(), &r.make_term()),
-            Free::Just(val, size) => Term::nat(*val, *size),
+            Free::Just(val, size) => Term::nat(*val as i64, *size),
             Free::Var(term) => term.clone(),
+            Free::Extend { val, size, signed } => val.make_term().extend(*size, *signed),
         }
     }
 }
@@ -96,22 +185,20 @@ static ALLOC: &str = r"
 
 static READ8: &str = r"
 (ptr) where {
-    val = @byte(ptr);
+    val = inspect @byte(ptr);
 } -> (ret) where {
     assert ret == val;
-    val;
 }";
 
 static READ32: &str = r"
 (ptr) where {
-    p0 = @byte(ptr + 0);
-    p1 = @byte(ptr + 1);
-    p2 = @byte(ptr + 2);
-    p3 = @byte(ptr + 3);
+    p0 = inspect @byte(ptr + 0);
+    p1 = inspect @byte(ptr + 1);
+    p2 = inspect @byte(ptr + 2);
+    p3 = inspect @byte(ptr + 3);
     let val = ((p3 << 24) + (p2 << 16)) + ((p1 << 8) + p0);
 } -> (ret) where {
     assert ret == val;
-    p0; p1; p2; p3;
 }";
 
 static WRITE8: &str = r"
@@ -145,12 +232,55 @@ impl Builtin {
     pub(super) fn infer(&self) -> Fun<NegTyp> {
         let files = builtins();
         match self {
-            Builtin::Alloc => desugar::convert_neg(&files, 0),
+            Builtin::Alloc(heap) => alloc(desugar::convert_neg(&files, 0), *heap),
             Builtin::Read8 => desugar::convert_neg(&files, 1),
             Builtin::Read32 => desugar::convert_neg(&files, 2),
             Builtin::Write8 => desugar::convert_neg(&files, 3),
             Builtin::Write32 => desugar::convert_neg(&files, 4),
             Builtin::Pack(typ) => typ.clone(),
+            Builtin::FuncPtr(idx, typ) => func_ptr(*idx, typ.clone()),
         }
     }
 }
+
+/// The allocated memory does not overlap with the data segments.
+fn alloc(typ: Fun<NegTyp>, heap: u32) -> Fun<NegTyp> {
+    let heap = Term::nat(heap as i64, 32);
+    let fun = typ.fun.clone();
+    Fun {
+        fun: Rc::new(move |h, terms| {
+            let NegTyp { arg, ret } = (fun)(h, terms)?;
+            let (heap, span, fun) = (heap.clone(), ret.span, ret.fun.clone());
+            let ret = Fun {
+                fun: Rc::new(move |h, terms| {
+                    let [start] = terms else { unreachable!() };
+                    h.assert(heap.ule(start), span)?;
+                    (fun)(h, terms)
+                }),
+                ..ret
+            };
+            Ok(NegTyp { arg, ret })
+        }),
+        ..typ
+    }
+}
+
+/// `() -> (ptr) where { assert ptr == idx; fn ptr typ; }`
+fn func_ptr(idx: usize, typ: Fun<NegTyp>) -> Fun<NegTyp> {
+    let span = typ.span;
+    let ret = Fun {
+        tau: vec![(32, "ptr".to_owned())],
+        span,
+        fun: Rc::new(move |heap, terms| {
+            let [ptr] = terms else { unreachable!() };
+            heap.assert(ptr.eq(&Term::nat(idx as i64, 32)), span)?;
+            heap.func(ptr.clone(), typ.clone())?;
+            Ok(PosTyp)
+        }),
+    };
+    Fun {
+        tau: vec![],
+        span,
+        fun: Rc::new(move |_heap, _terms| Ok(NegTyp::new(ret.clone()))),
+    }
+}
@@ -3,58 +3,121 @@ use std::{
     rc::{Rc, Weak},
 };
 
-use crate::{desugar::Desugar, parse, refinement::typing::zip_eq};
+use miette::{Diagnostic, SourceSpan};
+use thiserror::Error;
+
+use crate::{
+    desugar::{ConvertErr, Desugar},
+    parse,
+    refinement::typing::zip_eq,
+};
 
 use super::{builtin::Builtin, Expr, Free, Lambda, Thunk, Val, Value};
 
+/// An integer with its number of bits, the bits above its size are zero.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Int {
+    pub val: u64,
+    pub size: u32,
+}
+
+impl Int {
+    pub fn new(val: i64, size: u32) -> Self {
+        let mask = u64::MAX >> (64 - size);
+        Self {
+            val: val as u64 & mask,
+            size,
+        }
+    }
+
+    /// The value when the highest bit is the sign.
+    pub fn signed(&self) -> i64 {
+        let unused = 64 - self.size;
+        ((self.val << unused) as i64) >> unused
+    }
+}
+
+/// Whether asserts are evaluated at runtime.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AssertMode {
+    /// The program was checked, so asserts are ghost code.
+    #[default]
+    Ghost,
+    /// The program was not checked, so asserts can fail.
+    Checked,
+}
+
 #[derive(Default)]
 pub struct Memory {
     data: Vec<u8>,
+    asserts: AssertMode,
+    // top-level functions that function pointers index into
+    table: Vec<Rc<Lambda<Int>>>,
 }
 
 impl Memory {
-    pub fn new(data: Vec<u8>) -> Self {
-        Self { data }
+    pub fn new(data: Vec<u8>, asserts: AssertMode, table: Vec<Rc<Lambda<Int>>>) -> Self {
+        Self {
+            data,
+            asserts,
+            table,
+        }
     }
 }
 
-impl Free<i32> {
-    pub fn eval(&self) -> i32 {
-        match self {
-            Free::Just(idx, _size) => *idx as i32,
+impl Free<Int> {
+    pub fn eval(&self) -> Result<Int, DivErr> {
+        let res = match self {
+            Free::Just(val, size) => Int::new(*val as i64, *size),
             Free::Var(local) => *local,
-            Free::BinOp { l, r, op } => op.eval(l.eval(), r.eval()),
-        }
+            Free::BinOp { l, r, op } => op.eval(l.eval()?, r.eval()?).ok_or(DivErr)?,
+            Free::Extend { val, size, signed } => {
+                let val = val.eval()?;
+                let extended = if *signed {
+                    val.signed()
+                } else {
+                    val.val as i64
+                };
+                Int::new(extended, *size)
+            }
+        };
+        Ok(res)
     }
 }
 
-impl Value<i32> {
-    pub fn to_vec(&self) -> Vec<i32> {
+impl Value<Int> {
+    pub fn to_vec(&self) -> Result<Vec<Int>, DivErr> {
         self.inj.iter().map(|inj| inj.eval()).collect()
     }
 }
 
-impl Val for i32 {
-    type Func = Rc<Lambda<i32>>;
+impl Val for Int {
+    type Func = Rc<Lambda<Int>>;
+    fn size(&self) -> u32 {
+        self.size
+    }
     fn make(
         _this: &Desugar<Self>,
+        _name: &str,
         lamb: &Weak<Lambda<Self>>,
         _typ: &parse::types::NegTyp,
-    ) -> Self::Func {
-        lamb.upgrade().unwrap()
+    ) -> Result<Self::Func, ConvertErr> {
+        // labels are made for a lambda that is being defined or instantiated,
+        // or for a top-level function that `Desugared` keeps alive
+        Ok(lamb.upgrade().unwrap())
     }
 }
 
 impl Memory {
-    pub fn eval(&mut self, expr: Expr<i32>) -> Vec<i32> {
+    pub fn eval(&mut self, expr: Expr<Int>) -> Result<Vec<Int>, EvalErr> {
         let mut owned = expr;
         let mut borrow = &owned;
         loop {
             match borrow {
-                Expr::Return(val) => return val.to_vec(),
+                Expr::Return(val) => return Ok(val.to_vec()?),
                 Expr::App(func, arg, e) => {
-                    let arg = self.call_func(arg, func);
-                    owned = e.inst(&arg).val;
+                    let arg = self.call_func(arg, func)?;
+                    owned = e.inst(&arg)?.val;
                     borrow = &owned;
                 }
                 Expr::Cont(_cont, _lamb, e) => {
@@ -62,14 +125,21 @@ impl Memory {
                 }
                 Expr::Match(local, e) => {
                     // clip index because last branch is the default
-                    let idx = cmp::min(local.eval() as usize, e.len() - 1);
+                    let idx = cmp::min(local.eval()?.val, e.len() as u64 - 1);
+                    let idx = idx as usize;
                     borrow = &e[idx].val;
                 }
                 Expr::Loop(func, arg) => {
-                    let arg = arg.to_vec();
-                    owned = func.inst(&arg).val;
+                    let arg = arg.to_vec()?;
+                    owned = func.inst(&arg)?.val;
                     borrow = &owned;
                 }
+                Expr::Assert(prop, e) => {
+                    if self.asserts == AssertMode::Checked && prop.to_vec()?[0].val == 0 {
+                        return Err(AssertErr { span: prop.span }.into());
+                    }
+                    borrow = &e.val;
+                }
                 Expr::Debug(e) => {
                     borrow = &e.val;
                 }
@@ -77,43 +147,86 @@ impl Memory {
         }
     }
 
-    fn call_func(&mut self, arg: &Value<i32>, func: &Thunk<i32>) -> Vec<i32> {
-        let arg = arg.to_vec();
+    fn call_func(&mut self, arg: &Value<Int>, func: &Thunk<Int>) -> Result<Vec<Int>, EvalErr> {
+        let arg = arg.to_vec()?;
+        let ints: Vec<_> = arg.iter().map(|arg| arg.val).collect();
         match func {
             Thunk::Local(func) => {
-                let expr = func.inst(&arg).val;
+                let expr = func.inst(&arg)?.val;
                 self.eval(expr)
             }
-            Thunk::Builtin(builtin) => match builtin {
+            Thunk::Indirect { ptr, .. } => {
+                let ptr = ptr.eval()?.val;
+                let Some(func) = self.table.get(ptr as usize).cloned() else {
+                    return Err(TableErr { ptr }.into());
+                };
+                let expr = func.inst(&arg)?.val;
+                self.eval(expr)
+            }
+            Thunk::Builtin(builtin) => Ok(match builtin {
                 Builtin::Read8 => {
-                    let [ptr] = *arg else { panic!() };
-                    vec![self.data[ptr as usize] as i32]
+                    let [ptr] = *ints else { panic!() };
+                    vec![Int::new(self.data[ptr as usize] as i64, 32)]
                 }
                 Builtin::Read32 => {
-                    let [ptr] = *arg else { panic!() };
+                    let [ptr] = *ints else { panic!() };
                     let data = &self.data[ptr as usize..][..4];
-                    let val = i32::from_le_bytes(data.try_into().unwrap());
-                    vec![val]
+                    let val = u32::from_le_bytes(data.try_into().unwrap());
+                    vec![Int::new(val as i64, 32)]
                 }
                 Builtin::Write8 => {
-                    let [ptr, val] = *arg else { panic!() };
+                    let [ptr, val] = *ints else { panic!() };
                     self.data[ptr as usize] = val as u8;
                     vec![]
                 }
                 Builtin::Write32 => {
-                    let [ptr, val] = *arg else { panic!() };
-                    zip_eq(&mut self.data[ptr as usize..], val.to_le_bytes())
+                    let [ptr, val] = *ints else { panic!() };
+                    zip_eq(&mut self.data[ptr as usize..], (val as u32).to_le_bytes())
                         .for_each(|(d, s)| *d = s);
                     vec![]
                 }
                 Builtin::Pack(_) => vec![],
-                Builtin::Alloc => {
-                    let [bytes] = *arg else { panic!() };
+                Builtin::FuncPtr(idx, _) => vec![Int::new(*idx as i64, 32)],
+                Builtin::Alloc(_) => {
+                    let [bytes] = *ints else { panic!() };
                     let start = self.data.len();
                     self.data.resize(start + bytes as usize, 0);
-                    vec![start as i32]
+                    vec![Int::new(start as i64, 32)]
                 }
-            },
+            }),
         }
     }
 }
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum EvalErr {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Assert(#[from] AssertErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Convert(#[from] ConvertErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Div(#[from] DivErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Table(#[from] TableErr),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Division by zero at runtime")]
+pub struct DivErr;
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The value {ptr} does not point to a function")]
+pub struct TableErr {
+    ptr: u64,
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The assertion failed at runtime")]
+pub struct AssertErr {
+    #[label = "The assertion"]
+    span: Option<SourceSpan>,
+}
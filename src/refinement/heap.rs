@@ -3,14 +3,20 @@ use miette::{Diagnostic, SourceSpan};
 use thiserror::Error;
 use z3::ast::BV;
 
-use crate::{refinement::Hint, solver::ctx};
+use crate::{desugar::ConvertErr, refinement::Hint, solver::ctx};
 
 use super::{
-    func_term::FuncTerm, term::Term, verify::format_model, CtxForall, Forall, PosTyp, Resource,
-    SubContext, Switch,
+    func_term::FuncTerm, term::Term, verify::format_model, CtxForall, Forall, Fun, InnerDiagnostic,
+    NegTyp, PosTyp, Resource, SubContext, Switch,
 };
 
-pub(super) struct HeapConsume<'a>(pub &'a mut SubContext, pub Vec<CtxForall>, pub Term);
+/// The last field is set when resources are only inspected and never removed.
+pub(super) struct HeapConsume<'a>(
+    pub &'a mut SubContext,
+    pub Vec<CtxForall>,
+    pub Term,
+    pub bool,
+);
 
 impl<'a> std::ops::DerefMut for HeapConsume<'a> {
     fn deref_mut(&mut self) -> &mut Self::Target {
@@ -26,7 +32,16 @@ impl<'a> std::ops::Deref for HeapConsume<'a> {
     }
 }
 
-pub(super) struct HeapProduce<'a>(pub &'a mut SubContext, pub Vec<CtxForall>);
+/// New resources are added to the context right away, so that they can be inspected.
+/// The second field has the new resources, the third field has resources
+/// that were inspected and already existed.
+/// The last field is set when all new resources are only borrowed.
+pub(super) struct HeapProduce<'a>(
+    pub &'a mut SubContext,
+    pub Vec<CtxForall>,
+    pub Vec<CtxForall>,
+    pub bool,
+);
 
 impl<'a> std::ops::DerefMut for HeapProduce<'a> {
     fn deref_mut(&mut self) -> &mut Self::Target {
@@ -61,12 +76,16 @@ impl ForallRes {
     }
 }
 
+/// Resources that are not `moved` stay with their owner and can only be inspected.
 pub trait Heap {
-    fn exactly(&mut self, forall: CtxForall) -> Result<(), ConsumeErr>;
+    fn exactly(&mut self, forall: CtxForall, moved: bool) -> Result<(), ConsumeErr>;
 
     fn assert(&mut self, phi: Term, span: Option<SourceSpan>) -> Result<(), ConsumeErr>;
-    fn forall(&mut self, forall: Forall) -> Result<(), ConsumeErr>;
+    fn forall(&mut self, forall: Forall, moved: bool) -> Result<(), ConsumeErr>;
     fn once(&mut self, switch: Switch) -> Result<(), ConsumeErr>;
+    fn func(&mut self, ptr: Term, typ: Fun<NegTyp>) -> Result<(), ConsumeErr>;
+    /// New resources that are owned can not overlap with the data segments.
+    fn outside(&mut self, data: Forall, args: SourceSpan) -> Result<(), ConsumeErr>;
 
     #[allow(clippy::type_complexity)]
     fn apply(
@@ -76,9 +95,13 @@ pub trait Heap {
 }
 
 impl Heap for HeapConsume<'_> {
-    fn exactly(&mut self, forall: CtxForall) -> Result<(), ConsumeErr> {
+    fn exactly(&mut self, forall: CtxForall, moved: bool) -> Result<(), ConsumeErr> {
         // TODO: need to modify mask?
-        let got = self.try_remove(forall.have.clone())?;
+        let got = if moved && !self.3 {
+            self.take(forall.have.clone())?
+        } else {
+            self.inspect_reserved(forall.have.clone())?
+        };
         for removal in got.removals {
             self.assume
                 .masked_equal(&removal.have, &removal.value, &forall.value);
@@ -89,10 +112,14 @@ impl Heap for HeapConsume<'_> {
 
     /// We can first look at aggregate resources of the correct name.
     /// After that we can iterate over the remaining resources one by one.
-    fn forall(&mut self, mut need: Forall) -> Result<(), ConsumeErr> {
+    fn forall(&mut self, mut need: Forall, moved: bool) -> Result<(), ConsumeErr> {
         need.mask = need.mask.and(&FuncTerm::always(self.2.clone()));
 
-        let res = self.try_remove(need.clone())?;
+        let res = if moved && !self.3 {
+            self.take(need)?
+        } else {
+            self.inspect_reserved(need)?
+        };
 
         self.1.extend(res.removals);
         Ok(())
@@ -111,7 +138,26 @@ impl Heap for HeapConsume<'_> {
             mask: FuncTerm::exactly(&switch.args).and(&FuncTerm::always(switch.cond)),
             span: switch.span,
         };
-        self.forall(forall)
+        self.forall(forall, switch.moved)
+    }
+
+    /// One of the known functions for the pointer has to satisfy the contract.
+    fn func(&mut self, ptr: Term, typ: Fun<NegTyp>) -> Result<(), ConsumeErr> {
+        let mut err = ConsumeErr::UnknownFunc { contract: typ.span };
+        for have in self.known_funcs(&ptr) {
+            let mut ctx = self.without_alloc();
+            ctx.scope = Some(self.scope.clone().unwrap_or_default());
+            match ctx.sub_neg_type(&have, &typ) {
+                Ok(()) => return Ok(()),
+                Err(sub) => {
+                    err = ConsumeErr::Contract {
+                        contract: typ.span,
+                        err: InnerDiagnostic::new(sub),
+                    }
+                }
+            }
+        }
+        Err(err)
     }
 
     fn assert(&mut self, phi: Term, span: Option<SourceSpan>) -> Result<(), ConsumeErr> {
@@ -131,12 +177,17 @@ impl Heap for HeapConsume<'_> {
         Ok(())
     }
 
+    /// The resources of the caller never overlap with the data segments.
+    fn outside(&mut self, _data: Forall, _args: SourceSpan) -> Result<(), ConsumeErr> {
+        Ok(())
+    }
+
     fn apply(
         &mut self,
         f: Box<dyn FnOnce(&mut dyn Heap) -> Result<(), ConsumeErr>>,
     ) -> Result<ForallRes, ConsumeErr> {
-        let cond = self.2.clone();
-        let mut heap = HeapConsume(self, vec![], cond);
+        let (cond, inspect) = (self.2.clone(), self.3);
+        let mut heap = HeapConsume(self, vec![], cond, inspect);
         f(&mut heap)?;
         let new_forall = heap.1;
         self.1.extend(new_forall.clone());
@@ -146,23 +197,53 @@ impl Heap for HeapConsume<'_> {
     }
 }
 
+impl HeapProduce<'_> {
+    /// Resources that are not moved might still be owned by the context,
+    /// for example when they were lent to a function that returned.
+    fn existing(&mut self, have: &Forall, moved: bool) -> bool {
+        if moved && !self.3 {
+            return false;
+        }
+        let Ok(res) = self.inspect(have.clone()) else {
+            return false;
+        };
+        self.2.extend(res.removals);
+        true
+    }
+}
+
 impl Heap for HeapProduce<'_> {
-    fn exactly(&mut self, forall: CtxForall) -> Result<(), ConsumeErr> {
-        self.1.push(forall);
+    fn exactly(&mut self, mut forall: CtxForall, moved: bool) -> Result<(), ConsumeErr> {
+        if !self.existing(&forall.have, moved) {
+            forall.borrowed = !moved || self.3;
+            self.forall.push(forall.clone());
+            self.1.push(forall);
+        }
         Ok(())
     }
 
     /// Here we just put the aggregate to be used by consumption.
-    fn forall(&mut self, have: Forall) -> Result<(), ConsumeErr> {
+    fn forall(&mut self, have: Forall, moved: bool) -> Result<(), ConsumeErr> {
+        if self.existing(&have, moved) {
+            return Ok(());
+        }
         let forall = CtxForall {
             value: FuncTerm::free(&have.resource.arg_sizes()),
+            borrowed: !moved || self.3,
             have,
         };
+        self.forall.push(forall.clone());
         self.1.push(forall);
         Ok(())
     }
 
     fn once(&mut self, switch: Switch) -> Result<(), ConsumeErr> {
+        let forall = Forall {
+            resource: switch.resource.clone(),
+            mask: FuncTerm::exactly(&switch.args).and(&FuncTerm::always(switch.cond.clone())),
+            span: switch.span,
+        };
+
         if let Resource::Named(named) = &switch.resource {
             self.hints.push(Hint {
                 id: named.id,
@@ -170,17 +251,25 @@ impl Heap for HeapProduce<'_> {
             });
 
             if self.assume.is_always_true(switch.cond.to_bool()) {
-                (named.typ.fun)(self, &switch.args)?;
+                if self.existing(&forall, switch.moved) {
+                    return Ok(());
+                }
+                let borrowed = !switch.moved || self.3;
+                let mut heap = HeapProduce(self, vec![], vec![], borrowed);
+                (named.typ.fun)(&mut heap, &switch.args)?;
+                let (new, existing) = (heap.1, heap.2);
+                self.1.extend(new);
+                self.2.extend(existing);
                 return Ok(());
             };
         }
 
-        let forall = Forall {
-            resource: switch.resource.clone(),
-            mask: FuncTerm::exactly(&switch.args).and(&FuncTerm::always(switch.cond)),
-            span: switch.span,
-        };
-        self.forall(forall)
+        self.forall(forall, switch.moved)
+    }
+
+    fn func(&mut self, ptr: Term, typ: Fun<NegTyp>) -> Result<(), ConsumeErr> {
+        self.funcs.push((ptr, typ));
+        Ok(())
     }
 
     fn assert(&mut self, phi: Term, _span: Option<SourceSpan>) -> Result<(), ConsumeErr> {
@@ -188,29 +277,96 @@ impl Heap for HeapProduce<'_> {
         Ok(())
     }
 
+    fn outside(&mut self, data: Forall, args: SourceSpan) -> Result<(), ConsumeErr> {
+        let owned = self.1.iter().filter(|new| !new.borrowed);
+        for new in owned.filter(|new| new.have.resource == data.resource) {
+            let overlap = Forall {
+                resource: data.resource.clone(),
+                mask: new.have.mask.and(&data.mask),
+                span: None,
+            };
+            if self.assume.still_possible(&overlap) {
+                return Err(ConsumeErr::InData {
+                    resource: new.have.span,
+                    args,
+                });
+            }
+        }
+        Ok(())
+    }
+
     fn apply(
         &mut self,
         f: Box<dyn FnOnce(&mut dyn Heap) -> Result<(), ConsumeErr>>,
     ) -> Result<ForallRes, ConsumeErr> {
-        let mut heap = HeapProduce(self, vec![]);
+        let borrowed = self.3;
+        let mut heap = HeapProduce(self, vec![], vec![], borrowed);
         f(&mut heap)?;
-        let new_forall = heap.1;
+        let (new_forall, existing) = (heap.1, heap.2);
         self.1.extend(new_forall.clone());
+        self.2.extend(existing.clone());
         Ok(ForallRes {
-            removals: new_forall,
+            removals: new_forall.into_iter().chain(existing).collect(),
         })
     }
 }
 
 impl SubContext {
+    /// The contracts of the functions that the pointer is known to point to.
+    pub(super) fn known_funcs(&self, ptr: &Term) -> Vec<Fun<NegTyp>> {
+        let funcs = self.funcs.iter();
+        let same = funcs.filter(|(have, _)| self.assume.is_always_true(have.eq(ptr).to_bool()));
+        same.map(|(_, typ)| typ.clone()).collect()
+    }
+
+    /// Find the resource without removing it, borrowed resources can be inspected too.
+    fn inspect(&self, need: Forall) -> Result<ForallRes, ConsumeErr> {
+        self.clone().try_remove(need, true)
+    }
+
+    /// Inspect the resource and remember it, so that it can be checked again after the call.
+    fn inspect_reserved(&mut self, need: Forall) -> Result<ForallRes, ConsumeErr> {
+        self.inspected.push(need.clone());
+        self.inspect(need)
+    }
+
+    /// Inspected resources have to exist until the end of the call,
+    /// otherwise the same resource would be inspected and moved.
+    pub(super) fn check_inspected(&mut self) -> Result<(), ConsumeErr> {
+        for need in std::mem::take(&mut self.inspected) {
+            let resource = need.span;
+            self.inspect(need)
+                .map_err(|_| ConsumeErr::Inspected { resource })?;
+        }
+        Ok(())
+    }
+
+    /// Remove the resource, borrowed resources can not be moved.
+    fn take(&mut self, need: Forall) -> Result<ForallRes, ConsumeErr> {
+        let borrowed = self
+            .forall
+            .iter()
+            .any(|x| x.borrowed && x.have.resource == need.resource);
+        let before = borrowed.then(|| self.clone());
+
+        self.try_remove(need.clone(), false).map_err(|err| {
+            match before.map(|before| before.inspect(need.clone())) {
+                Some(Ok(_)) => ConsumeErr::Borrowed {
+                    resource: need.span,
+                },
+                _ => err,
+            }
+        })
+    }
+
     // we make sure to return the minimal set of loans that is sufficient
-    fn try_remove(&mut self, mut need: Forall) -> Result<ForallRes, ConsumeErr> {
+    fn try_remove(&mut self, mut need: Forall, inspect: bool) -> Result<ForallRes, ConsumeErr> {
         let mut removals = vec![];
 
         let mut allowed: Vec<_> = self
             .forall
             .iter_mut()
-            .filter(|x| x.have.resource == need.resource)
+            .filter(|x| x.have.resource == need.resource && (inspect || !x.borrowed))
             .collect();
 
         for i in 0..allowed.len() {
@@ -237,6 +393,7 @@ impl SubContext {
             removals.push(CtxForall {
                 have: overlap,
                 value: allowed[i].value.clone(),
+                borrowed: allowed[i].borrowed,
             });
         }
 
@@ -253,7 +410,7 @@ impl SubContext {
                 }
                 need.mask = need.mask.difference(&FuncTerm::exactly(&hint.args));
 
-                let mut consume = HeapConsume(self, vec![], cond);
+                let mut consume = HeapConsume(self, vec![], cond, inspect);
                 let PosTyp = (named.typ.fun)(&mut consume, &hint.args)?;
                 removals.extend(consume.1);
             }
@@ -265,9 +422,10 @@ impl SubContext {
 
         Err(ConsumeErr::MissingResource {
             resource: need.span,
-            help: self
-                .assume
-                .counter_example(need, &self.forall, self.scope.as_ref().unwrap()),
+            // resources are also looked up outside of values, there is no scope then
+            help: self.scope.as_ref().map_or(String::new(), |scope| {
+                self.assume.counter_example(need, &self.forall, scope)
+            }),
         })
     }
 }
@@ -277,6 +435,9 @@ pub enum ConsumeErr {
     #[error("Number of args is not equal")]
     NumArgs,
 
+    #[error("The value has {have} bits, but the type has {need} bits")]
+    Width { have: u32, need: u32 },
+
     #[error("The resource does not always exist")]
     MissingResource {
         #[label = "The resource"]
@@ -285,6 +446,41 @@ pub enum ConsumeErr {
         help: String,
     },
 
+    #[error("The resource is borrowed and can not be moved")]
+    Borrowed {
+        #[label = "The resource"]
+        resource: Option<SourceSpan>,
+    },
+
+    #[error("The resource might be in the data segments")]
+    #[diagnostic(help("The data segments are at the start of memory and can only be inspected"))]
+    InData {
+        #[label = "The resource"]
+        resource: Option<SourceSpan>,
+        #[label = "The arguments"]
+        args: SourceSpan,
+    },
+
+    #[error("The resource is inspected, but the same call also moves it")]
+    Inspected {
+        #[label = "The resource"]
+        resource: Option<SourceSpan>,
+    },
+
+    #[error("The function pointer does not satisfy the contract")]
+    Contract {
+        #[label = "The contract"]
+        contract: Option<SourceSpan>,
+        #[related]
+        err: InnerDiagnostic,
+    },
+
+    #[error("The value is not known to point to a function")]
+    UnknownFunc {
+        #[label = "The contract"]
+        contract: Option<SourceSpan>,
+    },
+
     #[error("The assertion is not always true")]
     InvalidAssert {
         #[label = "The assertion"]
@@ -292,4 +488,24 @@ pub enum ConsumeErr {
         #[help]
         help: String,
     },
+
+    #[error("The divisor might be zero")]
+    DivZero {
+        #[label = "The value"]
+        value: Option<SourceSpan>,
+        #[help]
+        help: String,
+    },
+
+    #[error("The signed division might overflow")]
+    DivOverflow {
+        #[label = "The value"]
+        value: Option<SourceSpan>,
+        #[help]
+        help: String,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Convert(#[from] ConvertErr),
 }
@@ -9,39 +9,46 @@ use crate::refinement::{
 use super::{heap::ConsumeErr, term::Term, Fun, InnerDiagnostic, NegTyp, PosTyp, Solved};
 
 impl SubContext {
-    pub fn extract<T>(&mut self, n: &Fun<T>) -> Solved<T> {
+    pub fn extract<T>(&mut self, n: &Fun<T>) -> Result<Solved<T>, ConsumeErr> {
         let mut terms = vec![];
         for (tau, prefix) in &n.tau {
             let term = Term::fresh(prefix, *tau);
             terms.push(term);
         }
 
-        let mut heap = HeapProduce(self, vec![]);
-        let typ = (n.fun)(&mut heap, &terms).unwrap();
-        let new_forall = heap.1;
-        self.forall.extend(new_forall);
+        let mut heap = HeapProduce(self, vec![], vec![], false);
+        let typ = (n.fun)(&mut heap, &terms)?;
 
-        Solved { inner: typ, terms }
+        Ok(Solved { inner: typ, terms })
     }
 
     pub fn with_terms<T>(&mut self, typ: &Fun<T>, terms: &[Term]) -> Result<T, ConsumeErr> {
-        let mut heap = HeapConsume(self, vec![], Term::bool(true));
+        self.inspected.clear();
+        let mut heap = HeapConsume(self, vec![], Term::bool(true), false);
 
         if typ.tau.len() != terms.len() {
             return Err(ConsumeErr::NumArgs);
         }
-        (typ.fun)(&mut heap, terms)
+        for ((need, _), term) in typ.tau.iter().zip(terms) {
+            let have = term.get_size();
+            if have != *need {
+                return Err(ConsumeErr::Width { have, need: *need });
+            }
+        }
+        let typ = (typ.fun)(&mut heap, terms)?;
+        self.check_inspected()?;
+        Ok(typ)
     }
 
     pub fn sub_pos_typ(mut self, q: &Fun<PosTyp>, p: &Fun<PosTyp>) -> Result<(), SubTypErr> {
-        let solved = self.extract(q);
+        let solved = self.extract(q).using(q, p)?;
         let PosTyp = self.with_terms(p, &solved.terms).using(q, p)?;
         self.check_empty().using(q, p)?;
         Ok(())
     }
 
     pub fn sub_neg_type(mut self, n: &Fun<NegTyp>, m: &Fun<NegTyp>) -> Result<(), SubTypErr> {
-        let solved = self.extract(m);
+        let solved = self.extract(m).using(n, m)?;
         let typ = self.with_terms(n, &solved.terms).using(n, m)?;
 
         self.sub_pos_typ(&typ.ret, &solved.ret)
@@ -74,6 +74,10 @@ impl Term {
         assert_eq!(self.get_size(), r.get_size());
         Self::BV(self.to_bv().bvudiv(&r.to_bv()))
     }
+    pub fn sdiv(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvsdiv(&r.to_bv()))
+    }
     pub fn mul(&self, r: &Self) -> Self {
         assert_eq!(self.get_size(), r.get_size());
         Self::BV(self.to_bv().bvmul(&r.to_bv()))
@@ -90,6 +94,10 @@ impl Term {
         assert_eq!(self.get_size(), r.get_size());
         Self::BV(self.to_bv().bvurem(&r.to_bv()))
     }
+    pub fn srem(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvsrem(&r.to_bv()))
+    }
     pub fn eq(&self, r: &Self) -> Self {
         assert_eq!(self.get_size(), r.get_size());
         Self::Bool(self.to_bv()._eq(&r.to_bv()))
@@ -102,6 +110,14 @@ impl Term {
         assert_eq!(self.get_size(), r.get_size());
         Self::Bool(self.to_bv().bvult(&r.to_bv()))
     }
+    pub fn sle(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::Bool(self.to_bv().bvsle(&r.to_bv()))
+    }
+    pub fn slt(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::Bool(self.to_bv().bvslt(&r.to_bv()))
+    }
     pub fn shl(&self, r: &Self) -> Self {
         assert_eq!(self.get_size(), r.get_size());
         Self::BV(self.to_bv().bvshl(&r.to_bv()))
@@ -110,6 +126,22 @@ impl Term {
         assert_eq!(self.get_size(), r.get_size());
         Self::BV(self.to_bv().bvlshr(&r.to_bv()))
     }
+    pub fn ashr(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvashr(&r.to_bv()))
+    }
+    pub fn bit_and(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvand(&r.to_bv()))
+    }
+    pub fn bit_or(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvor(&r.to_bv()))
+    }
+    pub fn bit_xor(&self, r: &Self) -> Self {
+        assert_eq!(self.get_size(), r.get_size());
+        Self::BV(self.to_bv().bvxor(&r.to_bv()))
+    }
     pub fn bool_and(&self, r: &Self) -> Self {
         assert_eq!(self.get_size(), r.get_size());
         Self::Bool(self.to_bool() & r.to_bool())
@@ -128,4 +160,14 @@ impl Term {
     pub fn extend_to(&self, size: u32) -> Self {
         Self::BV(self.to_bv().zero_ext(size - self.get_size()))
     }
+    pub fn sign_extend_to(&self, size: u32) -> Self {
+        Self::BV(self.to_bv().sign_ext(size - self.get_size()))
+    }
+    pub fn extend(&self, size: u32, signed: bool) -> Self {
+        if signed {
+            self.sign_extend_to(size)
+        } else {
+            self.extend_to(size)
+        }
+    }
 }
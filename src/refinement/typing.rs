@@ -7,10 +7,17 @@ use std::{
 use miette::{Diagnostic, LabeledSpan, SourceSpan};
 use thiserror::Error;
 
-use crate::{desugar::Desugar, error::AppendLabels, parse, refinement::Free};
+use crate::{
+    desugar::{ConvertErr, Desugar},
+    error::AppendLabels,
+    parse,
+    refinement::Free,
+};
 
 use super::{
-    term::Term, Expr, Fun, Lambda, NegTyp, PosTyp, Spanned, SubContext, Thunk, Val, Value,
+    heap::{ConsumeErr, Heap, HeapConsume},
+    term::Term,
+    Expr, Fun, Lambda, NegTyp, PosTyp, Spanned, SubContext, Thunk, Val, Value,
 };
 
 pub fn zip_eq<A: IntoIterator, B: IntoIterator>(
@@ -43,11 +50,15 @@ impl Fun<PosTyp> {
 
 impl Val for Term {
     type Func = Fun<NegTyp>;
+    fn size(&self) -> u32 {
+        self.get_size()
+    }
     fn make(
         this: &Desugar<Self>,
+        _name: &str,
         _lamb: &Weak<Lambda<Self>>,
         typ: &parse::types::NegTyp,
-    ) -> Self::Func {
+    ) -> Result<Self::Func, ConvertErr> {
         let mut types = this.types.clone();
         types.terms.extend(this.vars.clone());
         types.convert_neg(typ.clone())
@@ -55,51 +66,64 @@ impl Val for Term {
 }
 
 impl SubContext {
-    fn infer_func(&self, func: &Thunk<Term>) -> Fun<NegTyp> {
+    fn infer_func(&self, func: &Thunk<Term>, s: &Value<Term>) -> Result<Fun<NegTyp>, CheckErr> {
         match func {
-            Thunk::Local(local) => local.clone(),
-            Thunk::Builtin(builtin) => builtin.infer(),
+            Thunk::Local(local) => Ok(local.clone()),
+            Thunk::Builtin(builtin) => Ok(builtin.infer()),
+            Thunk::Indirect { ptr, .. } => {
+                let ptr = self.check_free(ptr, s.span)?;
+                let known = self.known_funcs(&ptr).into_iter().next();
+                Ok(known.ok_or(PtrErr { span: s.span })?)
+            }
         }
     }
 
-    fn calc_args(&mut self, val: &Value<Term>) -> Vec<Term> {
+    fn calc_args(&mut self, val: &Value<Term>) -> Result<Vec<Term>, ConsumeErr> {
         self.scope = val.scope.clone();
 
         let mut res = vec![];
         for inj in &val.inj {
-            res.push(self.check_free(inj))
+            res.push(self.check_free(inj, val.span)?)
         }
-        res
+        Ok(res)
     }
 
-    pub fn check_free(&self, free: &Free<Term>) -> Term {
-        match free {
-            Free::Just(idx, size) => Term::nat(*idx, *size),
+    /// The span is used to report divisions that might fail.
+    pub fn check_free(
+        &self,
+        free: &Free<Term>,
+        span: Option<SourceSpan>,
+    ) -> Result<Term, ConsumeErr> {
+        Ok(match free {
+            Free::Just(idx, size) => Term::nat(*idx as i64, *size),
             Free::Var(local) => local.clone(),
             Free::BinOp { l, r, op } => {
-                let (l, r) = (self.check_free(l), self.check_free(r));
-                self.check_binop(op, &l, &r);
+                let (l, r) = (self.check_free(l, span)?, self.check_free(r, span)?);
+                self.check_binop(op, &l, &r, span)?;
                 op.apply(&l, &r)
             }
-        }
+            Free::Extend { val, size, signed } => {
+                self.check_free(val, span)?.extend(*size, *signed)
+            }
+        })
     }
 
     // This resolves value determined indices in `p`
-    pub fn check_value(&mut self, v: &Value<Term>, p: &Fun<PosTyp>) -> Result<(), ValueErr> {
-        let p_args = self.calc_args(v);
+    pub fn check_value(&mut self, v: &Value<Term>, p: &Fun<PosTyp>) -> Result<(), CheckErr> {
+        let p_args = self.calc_args(v)?;
         let PosTyp = self.with_terms(p, &p_args).using_val(v, p)?;
         Ok(())
     }
 
-    pub fn spine(&mut self, n: &Fun<NegTyp>, s: &Value<Term>) -> Result<Fun<PosTyp>, ValueErr> {
-        let n_args = self.calc_args(s);
+    pub fn spine(&mut self, n: &Fun<NegTyp>, s: &Value<Term>) -> Result<Fun<PosTyp>, CheckErr> {
+        let n_args = self.calc_args(s)?;
         let typ = self.with_terms(n, &n_args).using_val(s, n)?;
         Ok(typ.ret)
     }
 
-    pub fn check_expr(mut self, l: &Lambda<Term>, n: &Fun<NegTyp>) -> Result<(), ValueErr> {
-        let neg = self.extract(n);
-        let e = l.inst(&neg.terms);
+    pub fn check_expr(mut self, l: &Lambda<Term>, n: &Fun<NegTyp>) -> Result<(), CheckErr> {
+        let neg = self.extract(n)?;
+        let e = l.inst(&neg.terms)?;
         self.check_expr_pos(&e, &neg.inner.ret)
     }
 
@@ -107,14 +131,14 @@ impl SubContext {
         mut self,
         expr: &Spanned<Expr<Term>>,
         p: &Fun<PosTyp>,
-    ) -> Result<(), ValueErr> {
+    ) -> Result<(), CheckErr> {
         match &expr.val {
             Expr::Return(v) => {
                 self.check_value(v, p)?;
                 self.check_empty().using(expr, p)?;
             }
             Expr::App(func, s, l) => {
-                let n = self.infer_func(func);
+                let n = self.infer_func(func, s)?;
                 let bound_p = self.spine(&n, s)?;
                 self.check_expr(l, &bound_p.arrow(p.clone()))?;
             }
@@ -123,7 +147,7 @@ impl SubContext {
                 self.check_expr_pos(e, p)?;
             }
             Expr::Match(free, pats) => {
-                let term = self.check_free(free);
+                let term = self.check_free(free, Some(expr.span))?;
                 let size = term.get_size();
                 let (last_e, pats) = pats.split_last().unwrap();
 
@@ -143,6 +167,15 @@ impl SubContext {
                 let res = self.spine(n, s)?;
                 self.sub_pos_typ(&res, p).using(expr, p)?;
             }
+            Expr::Assert(v, e) => {
+                let [phi] = &*self.calc_args(v)? else {
+                    unreachable!()
+                };
+                let mut heap = HeapConsume(&mut self, vec![], Term::bool(true), false);
+                heap.assert(phi.clone(), v.span)?;
+                self.assume.assumptions.push(phi.clone());
+                self.check_expr_pos(e, p)?;
+            }
             Expr::Debug(e) => {
                 eprintln!("start #debug");
                 for ctx in &self.forall {
@@ -159,13 +192,15 @@ impl SubContext {
             assume: self.assume.clone(),
             forall: vec![],
             hints: self.hints.clone(),
+            funcs: self.funcs.clone(),
             scope: None,
+            inspected: vec![],
         }
     }
 
     pub fn check_empty(self) -> Result<(), EmptyErr> {
         for ctx_forall in &self.forall {
-            if self.assume.still_possible(&ctx_forall.have) {
+            if !ctx_forall.borrowed && self.assume.still_possible(&ctx_forall.have) {
                 let span = ctx_forall.have.span;
                 return Err(EmptyErr { span });
             }
@@ -213,4 +248,28 @@ pub struct EmptyErr {
     span: Option<SourceSpan>,
 }
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("The called value is not known to point to a function")]
+pub struct PtrErr {
+    #[label = "The arguments"]
+    span: Option<SourceSpan>,
+}
+
 type ValueErr = AppendLabels;
+
+/// The ways in which an expression does not match its type.
+#[derive(Error, Diagnostic, Debug)]
+pub enum CheckErr {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Value(#[from] ValueErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Consume(#[from] ConsumeErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Ptr(#[from] PtrErr),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Convert(#[from] ConvertErr),
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{self, Write},
 };
 
@@ -181,8 +181,12 @@ pub fn format_model<F: Write>(
     model: Model<'static>,
     scope: &HashMap<String, Nested<Term>>,
 ) {
-    let mut scope = scope.clone();
-    scope.iter_mut().for_each(|(_key, val)| val.eval(&model));
+    // the results of reads and calls are bound to names that can not be written in the source
+    let scope = scope
+        .iter()
+        .filter(|(name, _)| !name.contains(['[', '(', '&']));
+    let mut scope: BTreeMap<_, _> = scope.map(|(name, val)| (name, val.clone())).collect();
+    scope.values_mut().for_each(|val| val.eval(&model));
 
     for (name, item) in scope {
         writeln!(f, "{name} = {item:?}").unwrap();
@@ -191,13 +195,15 @@ pub fn format_model<F: Write>(
 
 impl Nested<Term> {
     pub fn eval(&mut self, model: &Model<'static>) {
-        match self {
-            Nested::Resource(..) => panic!(),
-            Nested::Just(term) => {
-                *term = match term {
-                    Term::BV(bv) => Term::BV(model.eval(bv, true).unwrap()),
-                    Term::Bool(b) => Term::Bool(model.eval(b, true).unwrap()),
-                }
+        let terms = match self {
+            Nested::Resource(_, args) => args.iter_mut().collect(),
+            Nested::Just(term) => vec![term],
+            Nested::Const(..) => vec![],
+        };
+        for term in terms {
+            *term = match term {
+                Term::BV(bv) => Term::BV(model.eval(bv, true).unwrap()),
+                Term::Bool(b) => Term::Bool(model.eval(b, true).unwrap()),
             }
         }
     }
@@ -206,8 +212,9 @@ impl Nested<Term> {
 impl fmt::Debug for Nested<Term> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Nested::Resource(..) => panic!(),
+            Nested::Resource(_, args) => f.debug_list().entries(args).finish(),
             Nested::Just(val) => val.fmt(f),
+            Nested::Const(val, _) => val.fmt(f),
         }
     }
 }
@@ -0,0 +1,145 @@
+//! Lowering of checked programs to a WebAssembly module.
+//!
+//! Every top-level function becomes an exported wasm function.
+//! Nested functions are lifted to their own wasm function and receive
+//! the variables that were in scope at their definition as extra arguments.
+
+pub mod encode;
+mod lower;
+mod wat;
+
+pub use lower::{Label, Lower, Var};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    /// Integers with up to 32 bits are stored in an `i32`.
+    pub fn of(size: u32) -> Self {
+        if size > 32 {
+            ValType::I64
+        } else {
+            ValType::I32
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    CallIndirect {
+        params: Vec<ValType>,
+        results: Vec<ValType>,
+    },
+    ReturnCall(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load,
+    I32Load8U,
+    I32Store,
+    I32Store8,
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    Num(NumOp),
+}
+
+#[derive(Clone, Copy)]
+pub enum NumOp {
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtU,
+    I32LeU,
+    I32LtS,
+    I32LeS,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivU,
+    I32RemU,
+    I32DivS,
+    I32RemS,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32Shl,
+    I32ShrU,
+    I32ShrS,
+    I32WrapI64,
+    I32Extend8S,
+    I32Extend16S,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtU,
+    I64LeU,
+    I64GeU,
+    I64LtS,
+    I64LeS,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivU,
+    I64RemU,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrU,
+    I64ShrS,
+    I64ExtendI32U,
+    I64ExtendI32S,
+}
+
+pub struct Func {
+    pub name: String,
+    pub export: bool,
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+/// A module with a single linear memory and a bump allocated heap.
+pub struct Module {
+    pub funcs: Vec<Func>,
+    // function pointers are indices into this table of top-level functions
+    pub table: Vec<u32>,
+    // the data segments at the start of the memory, the heap starts after them
+    pub data: Vec<u8>,
+}
+
+impl Module {
+    /// The initial size of the memory in pages, it has room for at least the data segments.
+    pub fn pages(&self) -> u32 {
+        MEMORY_PAGES.max(self.data.len().div_ceil(PAGE_SIZE) as u32)
+    }
+}
+
+/// initial size of the memory in pages, the allocator grows it on demand
+pub const MEMORY_PAGES: u32 = 1;
+
+/// number of bytes in a page of memory
+pub const PAGE_SIZE: usize = 0x10000;
+
+/// index of the global that holds the end of the allocated heap
+pub const HEAP_END: u32 = 0;
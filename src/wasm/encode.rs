@@ -0,0 +1,345 @@
+use super::{Func, Instr, Module, NumOp, ValType, HEAP_END};
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+fn uleb(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn len(out: &mut Vec<u8>, len: usize) {
+    uleb(out, len as u64)
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    len(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(out: &mut Vec<u8>, id: u8, content: Vec<u8>) {
+    out.push(id);
+    len(out, content.len());
+    out.extend(content);
+}
+
+impl ValType {
+    fn encode(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+impl NumOp {
+    pub fn opcode(self) -> u8 {
+        match self {
+            NumOp::I32Eqz => 0x45,
+            NumOp::I32Eq => 0x46,
+            NumOp::I32Ne => 0x47,
+            NumOp::I32LtU => 0x49,
+            NumOp::I32LeU => 0x4d,
+            NumOp::I32LtS => 0x48,
+            NumOp::I32LeS => 0x4c,
+            NumOp::I32Add => 0x6a,
+            NumOp::I32Sub => 0x6b,
+            NumOp::I32Mul => 0x6c,
+            NumOp::I32DivU => 0x6e,
+            NumOp::I32RemU => 0x70,
+            NumOp::I32DivS => 0x6d,
+            NumOp::I32RemS => 0x6f,
+            NumOp::I32And => 0x71,
+            NumOp::I32Or => 0x72,
+            NumOp::I32Xor => 0x73,
+            NumOp::I32Shl => 0x74,
+            NumOp::I32ShrU => 0x76,
+            NumOp::I32ShrS => 0x75,
+            NumOp::I32WrapI64 => 0xa7,
+            NumOp::I32Extend8S => 0xc0,
+            NumOp::I32Extend16S => 0xc1,
+            NumOp::I64Eqz => 0x50,
+            NumOp::I64Eq => 0x51,
+            NumOp::I64Ne => 0x52,
+            NumOp::I64LtU => 0x54,
+            NumOp::I64LeU => 0x58,
+            NumOp::I64GeU => 0x5a,
+            NumOp::I64LtS => 0x53,
+            NumOp::I64LeS => 0x57,
+            NumOp::I64Add => 0x7c,
+            NumOp::I64Sub => 0x7d,
+            NumOp::I64Mul => 0x7e,
+            NumOp::I64DivU => 0x80,
+            NumOp::I64RemU => 0x82,
+            NumOp::I64DivS => 0x7f,
+            NumOp::I64RemS => 0x81,
+            NumOp::I64And => 0x83,
+            NumOp::I64Or => 0x84,
+            NumOp::I64Xor => 0x85,
+            NumOp::I64Shl => 0x86,
+            NumOp::I64ShrU => 0x88,
+            NumOp::I64ShrS => 0x87,
+            NumOp::I64ExtendI32U => 0xad,
+            NumOp::I64ExtendI32S => 0xac,
+        }
+    }
+}
+
+/// Function signatures, every signature is in the type section once.
+#[derive(Default)]
+struct Types(Vec<(Vec<ValType>, Vec<ValType>)>);
+
+impl Types {
+    fn index(&mut self, params: &[ValType], results: &[ValType]) -> usize {
+        let sig = (params.to_owned(), results.to_owned());
+        match self.0.iter().position(|t| *t == sig) {
+            Some(idx) => idx,
+            None => {
+                self.0.push(sig);
+                self.0.len() - 1
+            }
+        }
+    }
+}
+
+impl Instr {
+    fn encode(&self, out: &mut Vec<u8>, types: &mut Types) {
+        // alignment hint and offset, we never assume aligned pointers
+        let memarg = |out: &mut Vec<u8>| out.extend([0, 0]);
+        match self {
+            Instr::Unreachable => out.push(0x00),
+            Instr::Block => out.extend([0x02, 0x40]),
+            Instr::Loop => out.extend([0x03, 0x40]),
+            Instr::End => out.push(0x0b),
+            Instr::Br(depth) => {
+                out.push(0x0c);
+                uleb(out, *depth as u64);
+            }
+            Instr::BrIf(depth) => {
+                out.push(0x0d);
+                uleb(out, *depth as u64);
+            }
+            Instr::BrTable(targets, default) => {
+                out.push(0x0e);
+                len(out, targets.len());
+                for target in targets {
+                    uleb(out, *target as u64);
+                }
+                uleb(out, *default as u64);
+            }
+            Instr::Return => out.push(0x0f),
+            Instr::Call(idx) => {
+                out.push(0x10);
+                uleb(out, *idx as u64);
+            }
+            Instr::CallIndirect { params, results } => {
+                out.push(0x11);
+                len(out, types.index(params, results));
+                out.push(0x00);
+            }
+            Instr::ReturnCall(idx) => {
+                out.push(0x12);
+                uleb(out, *idx as u64);
+            }
+            Instr::Drop => out.push(0x1a),
+            Instr::Select => out.push(0x1b),
+            Instr::LocalGet(idx) => {
+                out.push(0x20);
+                uleb(out, *idx as u64);
+            }
+            Instr::LocalSet(idx) => {
+                out.push(0x21);
+                uleb(out, *idx as u64);
+            }
+            Instr::GlobalGet(idx) => {
+                out.push(0x23);
+                uleb(out, *idx as u64);
+            }
+            Instr::GlobalSet(idx) => {
+                out.push(0x24);
+                uleb(out, *idx as u64);
+            }
+            Instr::I32Load => {
+                out.push(0x28);
+                memarg(out);
+            }
+            Instr::I32Load8U => {
+                out.push(0x2d);
+                memarg(out);
+            }
+            Instr::I32Store => {
+                out.push(0x36);
+                memarg(out);
+            }
+            Instr::I32Store8 => {
+                out.push(0x3a);
+                memarg(out);
+            }
+            Instr::MemorySize => out.extend([0x3f, 0x00]),
+            Instr::MemoryGrow => out.extend([0x40, 0x00]),
+            Instr::I32Const(val) => {
+                out.push(0x41);
+                sleb(out, *val as i64);
+            }
+            Instr::I64Const(val) => {
+                out.push(0x42);
+                sleb(out, *val);
+            }
+            Instr::Num(op) => out.push(op.opcode()),
+        }
+    }
+}
+
+impl Func {
+    fn signature(&self) -> (&[ValType], &[ValType]) {
+        (&self.params, &self.results)
+    }
+
+    fn encode_code(&self, out: &mut Vec<u8>, types: &mut Types) {
+        let mut code = vec![];
+        len(&mut code, self.locals.len());
+        for local in &self.locals {
+            code.push(1);
+            code.push(local.encode());
+        }
+        for instr in &self.body {
+            instr.encode(&mut code, types);
+        }
+        Instr::End.encode(&mut code, types);
+
+        len(out, code.len());
+        out.extend(code);
+    }
+}
+
+impl Module {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(VERSION);
+
+        let mut types = Types::default();
+        let mut func_types = vec![];
+        for func in &self.funcs {
+            let (params, results) = func.signature();
+            func_types.push(types.index(params, results));
+        }
+
+        // the code is encoded first, indirect calls can add signatures
+        let mut code = vec![];
+        len(&mut code, self.funcs.len());
+        for func in &self.funcs {
+            func.encode_code(&mut code, &mut types);
+        }
+
+        let mut content = vec![];
+        len(&mut content, types.0.len());
+        for (params, results) in &types.0 {
+            content.push(0x60);
+            len(&mut content, params.len());
+            content.extend(params.iter().map(|p| p.encode()));
+            len(&mut content, results.len());
+            content.extend(results.iter().map(|r| r.encode()));
+        }
+        section(&mut out, 1, content);
+
+        let mut content = vec![];
+        len(&mut content, func_types.len());
+        for idx in func_types {
+            len(&mut content, idx);
+        }
+        section(&mut out, 3, content);
+
+        if !self.table.is_empty() {
+            let mut content = vec![1, 0x70, 0x00];
+            len(&mut content, self.table.len());
+            section(&mut out, 4, content);
+        }
+
+        let mut content = vec![1, 0x00];
+        uleb(&mut content, self.pages() as u64);
+        section(&mut out, 5, content);
+
+        let mut content = vec![1, ValType::I32.encode(), 0x01];
+        Instr::I32Const(self.data.len() as i32).encode(&mut content, &mut types);
+        Instr::End.encode(&mut content, &mut types);
+        section(&mut out, 6, content);
+
+        let mut content = vec![];
+        let exports: Vec<_> = self
+            .funcs
+            .iter()
+            .enumerate()
+            .filter(|f| f.1.export)
+            .collect();
+        len(&mut content, exports.len() + 2);
+        for (idx, func) in exports {
+            name(&mut content, &func.name);
+            content.push(0x00);
+            len(&mut content, idx);
+        }
+        name(&mut content, "memory");
+        content.extend([0x02, 0]);
+        name(&mut content, "heap_end");
+        content.push(0x03);
+        uleb(&mut content, HEAP_END as u64);
+        section(&mut out, 7, content);
+
+        if !self.table.is_empty() {
+            let mut content = vec![1, 0x00];
+            Instr::I32Const(0).encode(&mut content, &mut types);
+            Instr::End.encode(&mut content, &mut types);
+            len(&mut content, self.table.len());
+            for idx in &self.table {
+                len(&mut content, *idx as usize);
+            }
+            section(&mut out, 9, content);
+        }
+
+        section(&mut out, 10, code);
+
+        if !self.data.is_empty() {
+            let mut content = vec![1, 0x00];
+            Instr::I32Const(0).encode(&mut content, &mut types);
+            Instr::End.encode(&mut content, &mut types);
+            len(&mut content, self.data.len());
+            content.extend(&self.data);
+            section(&mut out, 11, content);
+        }
+
+        let mut names = vec![];
+        len(&mut names, self.funcs.len());
+        for (idx, func) in self.funcs.iter().enumerate() {
+            len(&mut names, idx);
+            name(&mut names, &func.name);
+        }
+        let mut content = vec![];
+        name(&mut content, "name");
+        content.push(1);
+        len(&mut content, names.len());
+        content.extend(names);
+        section(&mut out, 0, content);
+
+        out
+    }
+}
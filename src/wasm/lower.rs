@@ -0,0 +1,576 @@
+use std::{
+    collections::HashMap,
+    rc::{Rc, Weak},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    desugar::{ConvertErr, Desugar},
+    parse,
+    refinement::{builtin::Builtin, eval::Int, BinOp, Expr, Free, Lambda, Thunk, Val, Value},
+};
+
+use super::{Func, Instr, Module, NumOp, ValType, HEAP_END};
+
+/// A variable that lives in a wasm local of the function that uses it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Var {
+    id: u32,
+    size: u32,
+}
+
+impl Var {
+    fn fresh(size: u32) -> Self {
+        static VAR_ID: AtomicU32 = AtomicU32::new(0);
+        let id = VAR_ID.fetch_add(1, Ordering::Relaxed);
+        Self { id, size }
+    }
+}
+
+#[derive(Clone)]
+pub struct Label {
+    name: String,
+    lambda: Weak<Lambda<Var>>,
+    // variables from the defining scope and those that the visible labels need,
+    // these are passed as extra arguments
+    captures: Vec<Var>,
+    // the sizes of the arguments and return values
+    args: Vec<u32>,
+    rets: Vec<u32>,
+}
+
+impl Label {
+    fn key(&self) -> *const () {
+        self.lambda.as_ptr() as *const ()
+    }
+}
+
+impl Val for Var {
+    type Func = Label;
+    fn size(&self) -> u32 {
+        self.size
+    }
+    fn make(
+        desugar: &Desugar<Self>,
+        name: &str,
+        lamb: &Weak<Lambda<Self>>,
+        typ: &parse::types::NegTyp,
+    ) -> Result<Self::Func, ConvertErr> {
+        let mut scope: Vec<_> = desugar.vars.iter().collect();
+        scope.sort_by_key(|(name, _)| *name);
+
+        let mut captures = vec![];
+        for (_name, nested) in scope {
+            captures.extend(nested.flatten());
+        }
+        // the body can call the labels that it sees, so it needs their captures too,
+        // even when their names have been bound again since
+        let mut visible: Vec<_> = desugar.labels.iter().filter(|(n, _)| *n != name).collect();
+        visible.sort_by_key(|(name, _)| *name);
+        for (_name, (_typ, label)) in visible {
+            for var in &label.captures {
+                if !captures.contains(var) {
+                    captures.push(*var);
+                }
+            }
+        }
+
+        Ok(Label {
+            name: name.to_owned(),
+            lambda: lamb.clone(),
+            captures,
+            args: desugar.types.sizes(&typ.args.val.params)?,
+            rets: desugar.types.sizes(&typ.ret.val.params)?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Lower {
+    indices: HashMap<*const (), u32>,
+    funcs: Vec<Option<Func>>,
+    pending: Vec<(u32, Label, Rc<Lambda<Var>>, bool)>,
+    // lambdas are kept alive so that their address stays unique
+    lambdas: Vec<Rc<Lambda<Var>>>,
+    alloc: Option<u32>,
+    table: Vec<u32>,
+}
+
+impl Lower {
+    pub fn export(&mut self, name: &str, lambda: &Rc<Lambda<Var>>, args: Vec<u32>, rets: Vec<u32>) {
+        let label = Label {
+            name: name.to_owned(),
+            lambda: Rc::downgrade(lambda),
+            captures: vec![],
+            args,
+            rets,
+        };
+        // exported functions are in the table in the order of their export
+        let idx = self.declare(label, true);
+        self.table.push(idx);
+    }
+
+    fn declare(&mut self, label: Label, export: bool) -> u32 {
+        if let Some(idx) = self.indices.get(&label.key()) {
+            return *idx;
+        }
+        let idx = self.funcs.len() as u32;
+        self.funcs.push(None);
+        self.indices.insert(label.key(), idx);
+        // labels are declared by an export or by the `Expr::Cont` that holds their lambda
+        let lambda = label.lambda.upgrade().unwrap();
+        self.lambdas.push(lambda.clone());
+        self.pending.push((idx, label, lambda, export));
+        idx
+    }
+
+    fn index(&self, label: &Label) -> u32 {
+        self.indices[&label.key()]
+    }
+
+    fn alloc(&mut self) -> u32 {
+        if let Some(idx) = self.alloc {
+            return idx;
+        }
+        let idx = self.funcs.len() as u32;
+        self.funcs.push(Some(alloc_func()));
+        self.alloc = Some(idx);
+        idx
+    }
+
+    pub fn finish(mut self, data: Vec<u8>) -> Result<Module, ConvertErr> {
+        while let Some((idx, label, lambda, export)) = self.pending.pop() {
+            let func = FuncLower::new(&mut self, &label).lower(&lambda, export)?;
+            self.funcs[idx as usize] = Some(func);
+        }
+        Ok(Module {
+            // every empty slot was declared as pending and is filled above
+            funcs: self.funcs.into_iter().map(Option::unwrap).collect(),
+            table: self.table,
+            data,
+        })
+    }
+}
+
+struct FuncLower<'a> {
+    lower: &'a mut Lower,
+    label: &'a Label,
+    locals: HashMap<Var, u32>,
+    types: Vec<ValType>,
+    body: Vec<Instr>,
+    // number of blocks that are open inside the function body
+    depth: u32,
+    // whether the function jumps back to its own start
+    looped: bool,
+}
+
+impl<'a> FuncLower<'a> {
+    fn new(lower: &'a mut Lower, label: &'a Label) -> Self {
+        Self {
+            lower,
+            label,
+            locals: HashMap::new(),
+            types: vec![],
+            body: vec![],
+            depth: 0,
+            looped: false,
+        }
+    }
+
+    fn local(&mut self, var: Var) -> u32 {
+        let idx = self.types.len() as u32;
+        self.types.push(ValType::of(var.size));
+        self.locals.insert(var, idx);
+        idx
+    }
+
+    fn lower(mut self, lambda: &Lambda<Var>, export: bool) -> Result<Func, ConvertErr> {
+        let args: Vec<_> = self
+            .label
+            .args
+            .iter()
+            .map(|size| Var::fresh(*size))
+            .collect();
+        for var in args.iter().chain(&self.label.captures) {
+            self.local(*var);
+        }
+        let num_params = self.types.len();
+
+        let expr = lambda.inst(&args)?;
+        self.expr(&expr.val)?;
+
+        let body = if self.looped {
+            let mut body = vec![Instr::Loop];
+            body.extend(self.body);
+            body.extend([Instr::End, Instr::Unreachable]);
+            body
+        } else {
+            self.body
+        };
+
+        Ok(Func {
+            name: self.label.name.clone(),
+            export,
+            params: self.types[..num_params].to_owned(),
+            results: self
+                .label
+                .rets
+                .iter()
+                .map(|size| ValType::of(*size))
+                .collect(),
+            locals: self.types[num_params..].to_owned(),
+            body,
+        })
+    }
+
+    fn expr(&mut self, expr: &Expr<Var>) -> Result<(), ConvertErr> {
+        match expr {
+            Expr::Return(val) => {
+                self.value(val);
+                self.body.push(Instr::Return);
+            }
+            Expr::App(func, val, cont) => {
+                let rets = match func {
+                    Thunk::Local(label) => {
+                        self.value(val);
+                        self.captures(label);
+                        let idx = self.lower.index(label);
+                        self.body.push(Instr::Call(idx));
+                        label.rets.clone()
+                    }
+                    Thunk::Builtin(builtin) => vec![32; self.builtin(builtin, val)],
+                    Thunk::Indirect { ptr, args, rets } => {
+                        self.value(val);
+                        self.free(ptr);
+                        let types = |sizes: &[u32]| sizes.iter().map(|s| ValType::of(*s)).collect();
+                        self.body.push(Instr::CallIndirect {
+                            params: types(args),
+                            results: types(rets),
+                        });
+                        rets.clone()
+                    }
+                };
+                let res: Vec<_> = rets.iter().map(|size| Var::fresh(*size)).collect();
+                for var in res.iter().rev() {
+                    let idx = self.local(*var);
+                    self.body.push(Instr::LocalSet(idx));
+                }
+                self.expr(&cont.inst(&res)?.val)?;
+            }
+            Expr::Cont(_cont, label, e) => {
+                let mut label = label.clone();
+                label.name = format!("{}.{}", self.label.name, label.name);
+                self.lower.declare(label, false);
+                self.expr(&e.val)?;
+            }
+            Expr::Match(free, branches) => {
+                for _ in branches {
+                    self.body.push(Instr::Block);
+                    self.depth += 1;
+                }
+                self.free(free);
+                if free.size() > 32 {
+                    // the index is clipped before it fits in an `i32`
+                    let last = Instr::I64Const(branches.len() as i64 - 1);
+                    self.body.push(last.clone());
+                    self.free(free);
+                    self.body
+                        .extend([last, Instr::Num(NumOp::I64LtU), Instr::Select]);
+                    self.body.push(Instr::Num(NumOp::I32WrapI64));
+                }
+                let targets = (0..branches.len() as u32 - 1).collect();
+                self.body
+                    .push(Instr::BrTable(targets, branches.len() as u32 - 1));
+
+                // branch `i` follows the end of the `i`th block from the inside
+                for e in branches {
+                    self.body.push(Instr::End);
+                    self.depth -= 1;
+                    self.expr(&e.val)?;
+                }
+            }
+            Expr::Loop(label, val) => {
+                self.value(val);
+                if label.key() == self.label.key() {
+                    for idx in (0..label.args.len() as u32).rev() {
+                        self.body.push(Instr::LocalSet(idx));
+                    }
+                    self.body.push(Instr::Br(self.depth));
+                    self.looped = true;
+                } else {
+                    self.captures(label);
+                    let idx = self.lower.index(label);
+                    self.body.push(Instr::ReturnCall(idx));
+                }
+            }
+            // asserts are only checked statically
+            Expr::Assert(_, e) | Expr::Debug(e) => self.expr(&e.val)?,
+        }
+        Ok(())
+    }
+
+    fn captures(&mut self, label: &Label) {
+        for var in &label.captures {
+            self.body.push(Instr::LocalGet(self.locals[var]));
+        }
+    }
+
+    fn value(&mut self, val: &Value<Var>) {
+        for inj in &val.inj {
+            self.free(inj);
+        }
+    }
+
+    fn free(&mut self, free: &Free<Var>) {
+        match free {
+            Free::Var(var) => self.body.push(Instr::LocalGet(self.locals[var])),
+            Free::Just(val, 64) => self.body.push(Instr::I64Const(*val as i64)),
+            Free::Just(val, size) => {
+                let val = Int::new(*val as i64, *size).val as i32;
+                self.body.push(Instr::I32Const(val))
+            }
+            Free::Extend { val, size, signed } => {
+                self.free(val);
+                if *signed {
+                    self.sign_extend(val.size());
+                }
+                match (*size > 32, *signed) {
+                    (true, false) => self.body.push(Instr::Num(NumOp::I64ExtendI32U)),
+                    (true, true) => self.body.push(Instr::Num(NumOp::I64ExtendI32S)),
+                    (false, _) => self.wrap(*size),
+                }
+            }
+            Free::BinOp { l, r, op } => self.binop(l, r, op),
+        }
+    }
+
+    fn binop(&mut self, l: &Free<Var>, r: &Free<Var>, op: &BinOp) {
+        let size = l.size();
+        let wide = size > 32;
+        match op {
+            // overflow is checked by calculating with 64 bits
+            BinOp::MulSafe | BinOp::AddSafe if !wide => {
+                self.free(l);
+                self.body.push(Instr::Num(NumOp::I64ExtendI32U));
+                self.free(r);
+                self.body.push(Instr::Num(NumOp::I64ExtendI32U));
+                let op = match op {
+                    BinOp::MulSafe => NumOp::I64Mul,
+                    _ => NumOp::I64Add,
+                };
+                self.body.extend([
+                    Instr::Num(op),
+                    Instr::I64Const(size as i64),
+                    Instr::Num(NumOp::I64ShrU),
+                    Instr::Num(NumOp::I64Eqz),
+                ]);
+            }
+            // the sum does not overflow if it is not smaller than `l`
+            BinOp::AddSafe => {
+                self.free(l);
+                self.free(r);
+                self.body.push(Instr::Num(NumOp::I64Add));
+                self.free(l);
+                self.body.push(Instr::Num(NumOp::I64GeU));
+            }
+            // the product does not overflow if `l` is zero or `l * r / l == r`,
+            // `l` is replaced by one in the division when it is zero
+            BinOp::MulSafe => {
+                self.free(l);
+                self.body.push(Instr::Num(NumOp::I64Eqz));
+                self.free(l);
+                self.free(r);
+                self.body.push(Instr::Num(NumOp::I64Mul));
+                self.free(l);
+                self.free(l);
+                self.body.extend([
+                    Instr::Num(NumOp::I64Eqz),
+                    Instr::Num(NumOp::I64ExtendI32U),
+                    Instr::Num(NumOp::I64Or),
+                    Instr::Num(NumOp::I64DivU),
+                ]);
+                self.free(r);
+                self.body.push(Instr::Num(NumOp::I64Eq));
+                self.body.push(Instr::Num(NumOp::I32Or));
+            }
+            // `eqz` twice turns any non zero value into `1`
+            BinOp::And | BinOp::Or => {
+                let is_zero = if wide { NumOp::I64Eqz } else { NumOp::I32Eqz };
+                let not_zero = [Instr::Num(is_zero), Instr::Num(NumOp::I32Eqz)];
+                self.free(l);
+                self.body.extend(not_zero.clone());
+                self.free(r);
+                self.body.extend(not_zero);
+                let op = match op {
+                    BinOp::And => NumOp::I32And,
+                    _ => NumOp::I32Or,
+                };
+                self.body.push(Instr::Num(op));
+            }
+            // wasm takes the shift count modulo the width, larger counts shift out all bits
+            BinOp::Shl | BinOp::Shr | BinOp::SShr => {
+                let (width, less) = if wide {
+                    (64, NumOp::I64LtU)
+                } else {
+                    (32, NumOp::I32LtU)
+                };
+                let num = |val: i64| {
+                    if wide {
+                        Instr::I64Const(val)
+                    } else {
+                        Instr::I32Const(val as i32)
+                    }
+                };
+                self.free(l);
+                if let BinOp::SShr = op {
+                    // shifting by one less than the width still copies the sign bit
+                    self.sign_extend(size);
+                    self.free(r);
+                    self.body.push(num(width - 1));
+                } else {
+                    self.free(r);
+                    self.body.push(Instr::Num(op.num_op(wide)));
+                    self.body.push(num(0));
+                }
+                self.free(r);
+                self.body
+                    .extend([num(width), Instr::Num(less), Instr::Select]);
+                if let BinOp::SShr = op {
+                    self.body.push(Instr::Num(op.num_op(wide)));
+                }
+                self.wrap(size);
+            }
+            _ => {
+                // smaller integers are stored zero extended in an `i32`
+                let signed = matches!(
+                    op,
+                    BinOp::SDiv | BinOp::SRem | BinOp::SLess | BinOp::SLessEq
+                );
+                self.free(l);
+                if signed {
+                    self.sign_extend(size);
+                }
+                self.free(r);
+                if signed {
+                    self.sign_extend(size);
+                }
+                self.body.push(Instr::Num(op.num_op(wide)));
+                if op.size(size) == size {
+                    self.wrap(size);
+                }
+            }
+        }
+    }
+
+    /// Sign extend an integer with fewer bits to the whole `i32`.
+    fn sign_extend(&mut self, size: u32) {
+        match size {
+            8 => self.body.push(Instr::Num(NumOp::I32Extend8S)),
+            16 => self.body.push(Instr::Num(NumOp::I32Extend16S)),
+            _ => {}
+        }
+    }
+
+    /// Clear the bits of an `i32` above the size.
+    fn wrap(&mut self, size: u32) {
+        if size < 32 {
+            let mask = Int::new(-1, size).val as i32;
+            self.body
+                .extend([Instr::I32Const(mask), Instr::Num(NumOp::I32And)]);
+        }
+    }
+
+    fn builtin(&mut self, builtin: &Builtin, val: &Value<Var>) -> usize {
+        let (instr, rets) = match builtin {
+            Builtin::Read8 => (Instr::I32Load8U, 1),
+            Builtin::Read32 => (Instr::I32Load, 1),
+            Builtin::Write8 => (Instr::I32Store8, 0),
+            Builtin::Write32 => (Instr::I32Store, 0),
+            Builtin::Alloc(_) => (Instr::Call(self.lower.alloc()), 1),
+            Builtin::FuncPtr(idx, _) => (Instr::I32Const(*idx as i32), 1),
+            Builtin::Pack(_) => return 0,
+        };
+        self.value(val);
+        self.body.push(instr);
+        rets
+    }
+}
+
+impl BinOp {
+    fn num_op(&self, wide: bool) -> NumOp {
+        let (op, wide_op) = match self {
+            BinOp::Add => (NumOp::I32Add, NumOp::I64Add),
+            BinOp::Sub => (NumOp::I32Sub, NumOp::I64Sub),
+            BinOp::Div => (NumOp::I32DivU, NumOp::I64DivU),
+            BinOp::Mul => (NumOp::I32Mul, NumOp::I64Mul),
+            BinOp::Rem => (NumOp::I32RemU, NumOp::I64RemU),
+            BinOp::Eq => (NumOp::I32Eq, NumOp::I64Eq),
+            BinOp::Less => (NumOp::I32LtU, NumOp::I64LtU),
+            BinOp::LessEq => (NumOp::I32LeU, NumOp::I64LeU),
+            BinOp::NotEq => (NumOp::I32Ne, NumOp::I64Ne),
+            BinOp::Shl => (NumOp::I32Shl, NumOp::I64Shl),
+            BinOp::Shr => (NumOp::I32ShrU, NumOp::I64ShrU),
+            BinOp::SDiv => (NumOp::I32DivS, NumOp::I64DivS),
+            BinOp::SRem => (NumOp::I32RemS, NumOp::I64RemS),
+            BinOp::SLess => (NumOp::I32LtS, NumOp::I64LtS),
+            BinOp::SLessEq => (NumOp::I32LeS, NumOp::I64LeS),
+            BinOp::SShr => (NumOp::I32ShrS, NumOp::I64ShrS),
+            BinOp::BitAnd => (NumOp::I32And, NumOp::I64And),
+            BinOp::BitOr => (NumOp::I32Or, NumOp::I64Or),
+            BinOp::BitXor => (NumOp::I32Xor, NumOp::I64Xor),
+            BinOp::And | BinOp::Or | BinOp::MulSafe | BinOp::AddSafe => unreachable!(),
+        };
+        if wide {
+            wide_op
+        } else {
+            op
+        }
+    }
+}
+
+/// Bump allocator that grows the memory when the heap does not fit.
+fn alloc_func() -> Func {
+    let (bytes, start) = (0, 1);
+    let heap_pages = [
+        Instr::MemorySize,
+        Instr::I32Const(16),
+        Instr::Num(NumOp::I32Shl),
+    ];
+    let mut body = vec![
+        Instr::GlobalGet(HEAP_END),
+        Instr::LocalSet(start),
+        Instr::GlobalGet(HEAP_END),
+        Instr::LocalGet(bytes),
+        Instr::Num(NumOp::I32Add),
+        Instr::GlobalSet(HEAP_END),
+        Instr::Block,
+        Instr::GlobalGet(HEAP_END),
+    ];
+    body.extend(heap_pages.clone());
+    body.extend([
+        Instr::Num(NumOp::I32LeU),
+        Instr::BrIf(0),
+        Instr::GlobalGet(HEAP_END),
+    ]);
+    body.extend(heap_pages);
+    body.extend([
+        Instr::Num(NumOp::I32Sub),
+        Instr::I32Const(0xffff),
+        Instr::Num(NumOp::I32Add),
+        Instr::I32Const(16),
+        Instr::Num(NumOp::I32ShrU),
+        Instr::MemoryGrow,
+        Instr::Drop,
+        Instr::End,
+        Instr::LocalGet(start),
+    ]);
+
+    Func {
+        name: "@alloc".to_owned(),
+        export: false,
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+        locals: vec![ValType::I32],
+        body,
+    }
+}
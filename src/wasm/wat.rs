@@ -0,0 +1,240 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Write},
+};
+
+use indenter::indented;
+
+use super::{Func, Instr, Module, NumOp, ValType, HEAP_END};
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        }
+    }
+}
+
+impl NumOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            NumOp::I32Eqz => "i32.eqz",
+            NumOp::I32Eq => "i32.eq",
+            NumOp::I32Ne => "i32.ne",
+            NumOp::I32LtU => "i32.lt_u",
+            NumOp::I32LeU => "i32.le_u",
+            NumOp::I32LtS => "i32.lt_s",
+            NumOp::I32LeS => "i32.le_s",
+            NumOp::I32Add => "i32.add",
+            NumOp::I32Sub => "i32.sub",
+            NumOp::I32Mul => "i32.mul",
+            NumOp::I32DivU => "i32.div_u",
+            NumOp::I32RemU => "i32.rem_u",
+            NumOp::I32DivS => "i32.div_s",
+            NumOp::I32RemS => "i32.rem_s",
+            NumOp::I32And => "i32.and",
+            NumOp::I32Or => "i32.or",
+            NumOp::I32Xor => "i32.xor",
+            NumOp::I32Shl => "i32.shl",
+            NumOp::I32ShrU => "i32.shr_u",
+            NumOp::I32ShrS => "i32.shr_s",
+            NumOp::I32WrapI64 => "i32.wrap_i64",
+            NumOp::I32Extend8S => "i32.extend8_s",
+            NumOp::I32Extend16S => "i32.extend16_s",
+            NumOp::I64Eqz => "i64.eqz",
+            NumOp::I64Eq => "i64.eq",
+            NumOp::I64Ne => "i64.ne",
+            NumOp::I64LtU => "i64.lt_u",
+            NumOp::I64LeU => "i64.le_u",
+            NumOp::I64GeU => "i64.ge_u",
+            NumOp::I64LtS => "i64.lt_s",
+            NumOp::I64LeS => "i64.le_s",
+            NumOp::I64Add => "i64.add",
+            NumOp::I64Sub => "i64.sub",
+            NumOp::I64Mul => "i64.mul",
+            NumOp::I64DivU => "i64.div_u",
+            NumOp::I64RemU => "i64.rem_u",
+            NumOp::I64DivS => "i64.div_s",
+            NumOp::I64RemS => "i64.rem_s",
+            NumOp::I64And => "i64.and",
+            NumOp::I64Or => "i64.or",
+            NumOp::I64Xor => "i64.xor",
+            NumOp::I64Shl => "i64.shl",
+            NumOp::I64ShrU => "i64.shr_u",
+            NumOp::I64ShrS => "i64.shr_s",
+            NumOp::I64ExtendI32U => "i64.extend_i32_u",
+            NumOp::I64ExtendI32S => "i64.extend_i32_s",
+        }
+    }
+}
+
+fn types(f: &mut impl Write, kind: &str, types: &[ValType]) -> fmt::Result {
+    if types.is_empty() {
+        return Ok(());
+    }
+    write!(f, " ({kind}")?;
+    for typ in types {
+        write!(f, " {}", typ.name())?;
+    }
+    write!(f, ")")
+}
+
+/// Printable characters stay as they are, other bytes are written in hex.
+fn escape(data: &[u8]) -> String {
+    let mut out = String::new();
+    for &byte in data {
+        match byte {
+            b'"' | b'\\' => write!(out, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => out.push(byte as char),
+            _ => write!(out, "\\{byte:02x}").unwrap(),
+        }
+    }
+    out
+}
+
+struct FuncWat<'a> {
+    func: &'a Func,
+    id: &'a str,
+    ids: &'a [String],
+    // labels of the blocks that are currently open, innermost last
+    labels: Vec<String>,
+    blocks: usize,
+}
+
+impl FuncWat<'_> {
+    fn label(&self, depth: u32) -> &str {
+        &self.labels[self.labels.len() - 1 - depth as usize]
+    }
+
+    fn instr(&mut self, f: &mut impl Write, instr: &Instr) -> fmt::Result {
+        match instr {
+            Instr::Unreachable => write!(f, "unreachable"),
+            Instr::Block => {
+                let label = format!("$b{}", self.blocks);
+                self.blocks += 1;
+                write!(f, "block {label}")?;
+                self.labels.push(label);
+                Ok(())
+            }
+            Instr::Loop => {
+                let label = format!("${}", self.id);
+                write!(f, "loop {label}")?;
+                self.labels.push(label);
+                Ok(())
+            }
+            Instr::End => write!(f, "end"),
+            Instr::Br(depth) => write!(f, "br {}", self.label(*depth)),
+            Instr::BrIf(depth) => write!(f, "br_if {}", self.label(*depth)),
+            Instr::BrTable(targets, default) => {
+                write!(f, "br_table")?;
+                for target in targets.iter().chain([default]) {
+                    write!(f, " {}", self.label(*target))?;
+                }
+                Ok(())
+            }
+            Instr::Return => write!(f, "return"),
+            Instr::Call(idx) => write!(f, "call ${}", self.ids[*idx as usize]),
+            Instr::CallIndirect { params, results } => {
+                write!(f, "call_indirect")?;
+                types(f, "param", params)?;
+                types(f, "result", results)
+            }
+            Instr::ReturnCall(idx) => write!(f, "return_call ${}", self.ids[*idx as usize]),
+            Instr::Drop => write!(f, "drop"),
+            Instr::Select => write!(f, "select"),
+            Instr::LocalGet(idx) => write!(f, "local.get {idx}"),
+            Instr::LocalSet(idx) => write!(f, "local.set {idx}"),
+            Instr::GlobalGet(HEAP_END) => write!(f, "global.get $heap_end"),
+            Instr::GlobalSet(HEAP_END) => write!(f, "global.set $heap_end"),
+            Instr::GlobalGet(idx) => write!(f, "global.get {idx}"),
+            Instr::GlobalSet(idx) => write!(f, "global.set {idx}"),
+            Instr::I32Load => write!(f, "i32.load align=1"),
+            Instr::I32Load8U => write!(f, "i32.load8_u"),
+            Instr::I32Store => write!(f, "i32.store align=1"),
+            Instr::I32Store8 => write!(f, "i32.store8"),
+            Instr::MemorySize => write!(f, "memory.size"),
+            Instr::MemoryGrow => write!(f, "memory.grow"),
+            Instr::I32Const(val) => write!(f, "i32.const {val}"),
+            Instr::I64Const(val) => write!(f, "i64.const {val}"),
+            Instr::Num(op) => write!(f, "{}", op.name()),
+        }
+    }
+
+    fn write(mut self, f: &mut impl Write) -> fmt::Result {
+        let func = self.func;
+        write!(f, "(func ${}", self.id)?;
+        if func.export {
+            write!(f, " (export {:?})", func.name)?;
+        }
+        types(f, "param", &func.params)?;
+        types(f, "result", &func.results)?;
+        types(f, "local", &func.locals)?;
+        writeln!(f)?;
+
+        for instr in &func.body {
+            if let Instr::End = instr {
+                self.labels.pop();
+            }
+            // instructions are indented by the number of open blocks
+            let indent = "  ".repeat(self.labels.len() + 1);
+            let mut line = String::new();
+            self.instr(&mut line, instr)?;
+            writeln!(f, "{indent}{line}")?;
+        }
+        writeln!(f, ")")
+    }
+}
+
+impl Module {
+    /// Unique identifiers for all functions, nested functions can share a name.
+    fn ids(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut ids = vec![];
+        for (idx, func) in self.funcs.iter().enumerate() {
+            let mut id = func.name.clone();
+            if !seen.insert(id.clone()) {
+                id = format!("{id}#{idx}");
+            }
+            ids.push(id);
+        }
+        ids
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids = self.ids();
+
+        writeln!(f, "(module")?;
+        let mut inner = indented(f).with_str("  ");
+        writeln!(inner, "(memory (export \"memory\") {})", self.pages())?;
+        writeln!(
+            inner,
+            "(global $heap_end (export \"heap_end\") (mut i32) (i32.const {}))",
+            self.data.len()
+        )?;
+        if !self.data.is_empty() {
+            writeln!(inner, "(data (i32.const 0) \"{}\")", escape(&self.data))?;
+        }
+        if !self.table.is_empty() {
+            writeln!(inner, "(table {} funcref)", self.table.len())?;
+            write!(inner, "(elem (i32.const 0)")?;
+            for idx in &self.table {
+                write!(inner, " ${}", ids[*idx as usize])?;
+            }
+            writeln!(inner, ")")?;
+        }
+        for (func, id) in self.funcs.iter().zip(&ids) {
+            let wat = FuncWat {
+                func,
+                id,
+                ids: &ids,
+                labels: vec![],
+                blocks: 0,
+            };
+            wat.write(&mut inner)?;
+        }
+        writeln!(f, ")")
+    }
+}
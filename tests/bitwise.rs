@@ -0,0 +1,97 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+static OPS: &str = r"
+fn and(a, b) -> (x) where {
+    assert x <= a;
+    assert x <= b;
+} = {
+    return (a & b)
+}
+
+fn or(a, b) -> (x) where {
+    assert a <= x;
+    assert b <= x;
+} = {
+    return (a | b)
+}
+
+fn xor(a, b) -> (x) where {
+    assert (x ^ b) == a;
+} = {
+    return (a ^ b)
+}
+
+fn not(a) -> (x) where {
+    assert x == (0 - a) - 1;
+    assert (x & a) == 0;
+} = {
+    return (~a)
+}
+
+fn byte(a) -> (x) where {
+    assert x < 256;
+} = {
+    return (a & 0xFF)
+}
+
+fn logical_and(a, b) -> (x) where {
+    assert x <= 1;
+} = {
+    return (a && b)
+}
+
+fn logical_or(a, b) -> (x) where {
+    assert x <= 1;
+} = {
+    return (a || b)
+}
+";
+
+fn run(name: &str, args: Vec<i64>) -> Vec<i64> {
+    desugar::run(
+        MultiFile::from_code(OPS),
+        name,
+        args,
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap()
+}
+
+#[test]
+fn solver() {
+    desugar::check(&MultiFile::from_code(OPS)).unwrap();
+    let wrong =
+        "fn wrong(a) -> (x) where {\n    assert x < 255;\n} = {\n    return (a & 0xFF)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(wrong)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "While checking the value against the type, The assertion is not always true"
+    );
+}
+
+#[test]
+fn interpreter() {
+    assert_eq!(run("and", vec![0b1100, 0b1010]), [0b1000]);
+    assert_eq!(run("or", vec![0b1100, 0b1010]), [0b1110]);
+    assert_eq!(run("xor", vec![0b1100, 0b1010]), [0b0110]);
+    assert_eq!(run("not", vec![0]), [-1]);
+    assert_eq!(run("byte", vec![0x1234]), [0x34]);
+    // the logical operators only give zero or one
+    assert_eq!(run("logical_and", vec![2, 4]), [1]);
+    assert_eq!(run("logical_and", vec![2, 0]), [0]);
+    assert_eq!(run("logical_or", vec![0, 4]), [1]);
+    assert_eq!(run("logical_or", vec![0, 0]), [0]);
+}
+
+#[test]
+fn lowering() {
+    let wat = desugar::compile(MultiFile::from_code(OPS))
+        .unwrap()
+        .to_string();
+    for op in ["i32.and", "i32.or", "i32.xor", "i32.eqz"] {
+        assert!(wat.contains(op), "{op} is missing\n{wat}");
+    }
+    // `~a` is lowered as `a ^ -1`
+    assert!(wat.contains("i32.const -1\n    i32.xor"), "{wat}");
+}
@@ -0,0 +1,727 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+fn check_err(code: &str) -> String {
+    desugar::check(&MultiFile::from_code(code))
+        .unwrap_err()
+        .to_string()
+}
+
+static VEC3: &str = r"
+type vec3(ptr) where {
+    x = @byte(ptr + 0);
+    y = @byte(ptr + 1);
+    z = @byte(ptr + 2);
+}
+
+fn first(v: vec3) -> (x) where {
+    move vec3(v.ptr);
+} = {
+    let (x) = @read8(v.ptr + 0);
+    return (x)
+}
+
+fn twice(ptr) where {
+    move vec3(ptr);
+} -> (x) where {
+    move vec3(ptr);
+} = {
+    let (x) = first(ptr);
+    let (y) = first(ptr);
+    return (x + y)
+}
+";
+
+#[test]
+fn named_param() {
+    desugar::check(&MultiFile::from_code(VEC3)).unwrap();
+    let res = desugar::run(
+        MultiFile::from_code(VEC3),
+        "twice",
+        vec![1],
+        vec![0, 7, 8, 9],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [14]);
+
+    // passing the parameter consumes the named resource
+    let lost = format!("{VEC3}fn lost(v: vec3) -> (x) = {{\n    return (v.ptr)\n}}\n");
+    assert_eq!(
+        check_err(&lost),
+        "While checking the expression against the type, Can not leak resource"
+    );
+}
+
+static PEEK: &str = r"
+fn peek(ptr) where {
+    val = inspect @byte(ptr);
+} -> (ret) where {
+    assert ret == val;
+} = {
+    let (x) = @read8(ptr);
+    return (x)
+}
+
+fn both(a, b) where {
+    inspect @byte(b);
+    @byte(a);
+} -> () where {
+    @byte(a);
+} = {
+    return ()
+}
+";
+
+#[test]
+fn moved_and_inspected() {
+    // the caller keeps the inspected resource
+    let owner = r"
+fn owner(ptr) where {
+    @byte(ptr);
+} -> (ret) where {
+    @byte(ptr);
+} = {
+    let (a) = peek(ptr);
+    let (b) = peek(ptr);
+    return (a + b)
+}
+
+fn apart(p, q) where {
+    @byte(p);
+    @byte(q);
+} -> () where {
+    @byte(p);
+    @byte(q);
+} = {
+    both(p, q);
+    return ()
+}
+";
+    desugar::check(&MultiFile::from_code(&format!("{PEEK}{owner}"))).unwrap();
+
+    // a moved resource has to be returned, an inspected one does not
+    let drop = r"
+fn drop(ptr) where {
+    @byte(ptr);
+} -> () = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(drop),
+        "While checking the expression against the type, Can not leak resource"
+    );
+    let ignore = r"
+fn ignore(ptr) where {
+    inspect @byte(ptr);
+} -> () = {
+    return ()
+}
+";
+    desugar::check(&MultiFile::from_code(ignore)).unwrap();
+}
+
+#[test]
+fn borrowed() {
+    let steal = r"
+fn steal(ptr) where {
+    inspect @byte(ptr);
+} -> () where {
+    @byte(ptr);
+} = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(steal),
+        "While checking the value against the type, The resource is borrowed and can not be moved"
+    );
+    let write = r"
+fn write(ptr) where {
+    inspect @byte(ptr);
+} -> () = {
+    @write8(ptr, 1);
+    return ()
+}
+";
+    assert_eq!(
+        check_err(write),
+        "While checking the value against the type, The resource is borrowed and can not be moved"
+    );
+}
+
+#[test]
+fn inspect_and_move() {
+    let alias = r"
+fn alias(p) where {
+    @byte(p);
+} -> () where {
+    @byte(p);
+} = {
+    both(p, p);
+    return ()
+}
+";
+    assert_eq!(
+        check_err(&format!("{PEEK}{alias}")),
+        "While checking the value against the type, \
+        The resource is inspected, but the same call also moves it"
+    );
+}
+
+static HELLO: &str = r#"
+data HELLO = "hello";
+
+fn greet() -> (c) = {
+    let (c) = @read8(HELLO + 1);
+    return (c)
+}
+"#;
+
+#[test]
+fn data_segments() {
+    desugar::check(&MultiFile::from_code(HELLO)).unwrap();
+    let res = desugar::run(
+        MultiFile::from_code(HELLO),
+        "greet",
+        vec![],
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [b'e' as i64]);
+
+    // the arguments can only own memory after the data segments
+    let after = r"
+fn after(p) where {
+    assert (HELLO + 5) <= p;
+    @byte(p);
+} -> () where {
+    @byte(p);
+} = {
+    @write8(p, 1);
+    return ()
+}
+";
+    desugar::check(&MultiFile::from_code(&format!("{HELLO}{after}"))).unwrap();
+    let anywhere = r"
+fn anywhere(p) where {
+    @byte(p);
+} -> () where {
+    @byte(p);
+} = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(&format!("{HELLO}{anywhere}")),
+        "The resource might be in the data segments"
+    );
+    let overlap = r"
+type array(from, len) where {
+    @byte for (ptr) if (ptr - from) < len;
+}
+
+fn first(len) where {
+    assert 1 <= len;
+    arr = array(0, len);
+} -> (c) where {
+    arr;
+} = {
+    let (c) = @read8(0);
+    return (c)
+}
+";
+    assert_eq!(
+        check_err(&format!("{HELLO}{overlap}")),
+        "The resource might be in the data segments"
+    );
+}
+
+static CONSTS: &str = r"
+const SIZE = 4;
+const LAST = SIZE - 1;
+
+fn last(x) where {
+    assert x < SIZE;
+} -> (y) where {
+    assert y <= LAST;
+} = {
+    return (LAST - x)
+}
+";
+
+static DIGITS: &str = r#"
+data DIGITS = "012";
+
+fn digit(d) where {
+    assert d < 3;
+    assert DIGITS[d] != 0;
+} -> (c) where {
+    assert c == DIGITS[d];
+    assert c < 58;
+} = {
+    return (DIGITS[d])
+}
+"#;
+
+#[test]
+fn indexed_data() {
+    // data segments can be indexed like arrays, in the code and in the types
+    desugar::check(&MultiFile::from_code(DIGITS)).unwrap();
+    let source = MultiFile::from_code(DIGITS);
+    let res = desugar::run(source, "digit", vec![2], vec![], AssertMode::Ghost);
+    assert_eq!(res.unwrap(), [b'2' as i64]);
+
+    let wrong = DIGITS.replace("assert c < 58;", "assert c < 50;");
+    assert_eq!(
+        check_err(&wrong),
+        "While checking the value against the type, The assertion is not always true"
+    );
+}
+
+#[test]
+fn consts() {
+    desugar::check(&MultiFile::from_code(CONSTS)).unwrap();
+    let res = desugar::run(
+        MultiFile::from_code(CONSTS),
+        "last",
+        vec![1],
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [2]);
+    let too_large = format!("{CONSTS}fn wrong() -> (y) = {{\n    return last(SIZE)\n}}\n");
+    assert_eq!(
+        check_err(&too_large),
+        "While checking the value against the type, The assertion is not always true"
+    );
+
+    let div = "const A = 1 / 0;\n";
+    assert_eq!(check_err(div), "The constant divides by zero");
+    let rem = "const A = 2;\nconst B = 1 %$ (A - 2);\n";
+    assert_eq!(check_err(rem), "The constant divides by zero");
+    let effect = format!("{CONSTS}const C = last(1);\n");
+    assert_eq!(
+        check_err(&effect),
+        "Constants can not call functions, read arrays or take function pointers"
+    );
+    let ptr = format!("{CONSTS}const C = &last;\n");
+    assert_eq!(
+        check_err(&ptr),
+        "Constants can not call functions, read arrays or take function pointers"
+    );
+    let twice = "const A = 1;\ndata A = \"a\";\n";
+    assert_eq!(check_err(twice), "The name `A` is already defined");
+}
+
+static ARRAY_OF: &str = r"
+type vec3(ptr) where {
+    x = @byte(ptr + 0);
+    y = @byte(ptr + 1);
+    z = @byte(ptr + 2);
+}
+
+type array_of<T>(from, len, stride) where {
+    T for (p) if (p - from) % stride == 0 && p - from < len * stride;
+}
+";
+
+#[test]
+fn generic_instances() {
+    let same = r"
+fn same(p, n) where {
+    array_of<vec3>(p, n, 3);
+} -> () where {
+    array_of<vec3>(p, n, 3);
+} = {
+    return ()
+}
+";
+    desugar::check(&MultiFile::from_code(&format!("{ARRAY_OF}{same}"))).unwrap();
+    let other = r"
+fn other(p, n) where {
+    array_of<vec3>(p, n, 3);
+} -> () where {
+    array_of<@byte>(p, n, 3);
+} = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(&format!("{ARRAY_OF}{other}")),
+        "While checking the value against the type, The resource does not always exist"
+    );
+
+    let arity = r"
+type pairs<T>(from, len) where {
+    T for (p, q) if p - from < len;
+}
+
+fn pair(p) where {
+    pairs<vec3>(p, 1);
+} -> () = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(&format!("{ARRAY_OF}{arity}")),
+        "Expected 1 values, but 2 were given"
+    );
+}
+
+static APPLY: &str = r"
+fn double(x) -> (y) where {
+    assert y == x + x;
+} = {
+    return (x + x)
+}
+
+fn same(x) -> (y) = {
+    return (x)
+}
+
+fn apply(f, x) where {
+    fn f(a) -> (b) where {
+        assert b == a + a;
+    };
+} -> (y) where {
+    assert y == x + x;
+} = {
+    let (y) = f(x);
+    return (y)
+}
+";
+
+#[test]
+fn indirect() {
+    let good = format!("{APPLY}fn good(x) -> (y) = {{\n    return apply(&double, x)\n}}\n");
+    desugar::check(&MultiFile::from_code(&good)).unwrap();
+    let res = desugar::run(
+        MultiFile::from_code(&good),
+        "good",
+        vec![3],
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [6]);
+
+    let bad = format!("{APPLY}fn bad(x) -> (y) = {{\n    return apply(&same, x)\n}}\n");
+    assert_eq!(
+        check_err(&bad),
+        "While checking the value against the type, The function pointer does not satisfy the contract"
+    );
+    let unknown = format!("{APPLY}fn unknown(p, x) -> (y) = {{\n    return apply(p, x)\n}}\n");
+    assert_eq!(
+        check_err(&unknown),
+        "While checking the value against the type, The value is not known to point to a function"
+    );
+    let err = desugar::run(
+        MultiFile::from_code(&unknown),
+        "unknown",
+        vec![7, 1],
+        vec![],
+        AssertMode::Checked,
+    );
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "The value 7 does not point to a function"
+    );
+}
+
+#[test]
+fn shadowed_contract() {
+    let shadow = r"
+fn shadow(f, x) where {
+    fn f(a) -> (b);
+} -> (y) = {
+    let (f) = same(x);
+    let (y) = f(x);
+    return (y)
+}
+";
+    assert_eq!(
+        check_err(&format!("{APPLY}{shadow}")),
+        "Can not find variable"
+    );
+    let inner = r"
+fn outer(f, x) where {
+    fn f(a) -> (b);
+} -> (y) = {
+    fn inner(f) -> (y) = {
+        let (y) = f(f);
+        return (y)
+    };
+    return inner(x)
+}
+";
+    assert_eq!(
+        check_err(&format!("{APPLY}{inner}")),
+        "Can not find variable"
+    );
+}
+
+static READS: &str = r"
+type array(from, len) where {
+    @byte for (ptr) if ptr - from < len;
+}
+
+fn get(a, i) where {
+    assert i < 4;
+    arr = array(a, 4);
+} -> (v) where {
+    arr;
+} = {
+    let (v) = id(a[i]);
+    return (v)
+}
+
+fn id(x) -> (y) = {
+    return (x)
+}
+";
+
+#[test]
+fn array_reads() {
+    desugar::check(&MultiFile::from_code(READS)).unwrap();
+    let res = desugar::run(
+        MultiFile::from_code(READS),
+        "get",
+        vec![1, 2],
+        vec![0, 7, 8, 9, 10],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [9]);
+
+    let small = r"
+fn small(a, i) where {
+    assert i < 4;
+    arr = array(a, 4);
+} -> () where {
+    arr;
+} = {
+    assert a[i] < 10;
+    return ()
+}
+";
+    let err = desugar::check(&MultiFile::from_code(&format!("{READS}{small}"))).unwrap_err();
+    assert_eq!(err.to_string(), "The assertion is not always true");
+    // the model only has names from the source, in order
+    let help = err.help().unwrap().to_string();
+    let model = help.lines().filter_map(|line| line.strip_prefix("    "));
+    let names: Vec<_> = model
+        .map(|line| line.split(" = ").next().unwrap())
+        .collect();
+    assert_eq!(names, ["a", "i"], "{help}");
+
+    let nested = r"
+fn nested(a, i) where {
+    arr = array(a, 4);
+} -> (v) where {
+    arr;
+} = {
+    let (v) = id(a[i][i]);
+    return (v)
+}
+";
+    assert_eq!(
+        check_err(&format!("{READS}{nested}")),
+        "Arrays can only be indexed once"
+    );
+
+    let guarded = r"
+fn guarded(a, i) where {
+    arr = array(a, 4);
+} -> (v) where {
+    arr;
+} = {
+    let (v) = id((i < 4) && (a[i] == 0));
+    return (v)
+}
+";
+    assert_eq!(
+        check_err(&format!("{READS}{guarded}")),
+        "Reads are done before the statement, so they can not be on the right of `&&` or `||`"
+    );
+}
+
+static SET: &str = r"
+fn set(p, v) where {
+    @byte(p);
+} -> (o) where {
+    @byte(p);
+} = {
+    @write8(p, v);
+    return (v)
+}
+
+fn id(x) -> (y) = {
+    return (x)
+}
+";
+
+#[test]
+fn call_order() {
+    let order = r"
+fn order(p) where {
+    @byte(p);
+} -> (r) where {
+    @byte(p);
+} = {
+    let (x) = id(set(p, 1) + set(p, 2));
+    let (r) = @read8(p);
+    return (r)
+}
+
+fn nested(p) where {
+    @byte(p);
+} -> (r) where {
+    @byte(p);
+} = {
+    let (x) = set(p, set(p, 3) + 1);
+    let (r) = @read8(p);
+    return (r)
+}
+";
+    let code = format!("{SET}{order}");
+    desugar::check(&MultiFile::from_code(&code)).unwrap();
+    let run = |name| {
+        desugar::run(
+            MultiFile::from_code(&code),
+            name,
+            vec![0],
+            vec![0],
+            AssertMode::Ghost,
+        )
+    };
+    assert_eq!(run("order").unwrap(), [2]);
+    assert_eq!(run("nested").unwrap(), [4]);
+}
+
+#[test]
+fn call_errors() {
+    let pair = r"
+fn pair(x) -> (a, b) = {
+    return (x, x)
+}
+
+fn first(x) -> (y) = {
+    return (pair(x))
+}
+";
+    assert_eq!(
+        check_err(&format!("{SET}{pair}")),
+        "Only functions with one return value can be called in a value"
+    );
+    let in_type = r"
+fn typed(x) where {
+    assert id(x) == 1;
+} -> () = {
+    return ()
+}
+";
+    assert_eq!(
+        check_err(&format!("{SET}{in_type}")),
+        "Functions can not be used in types"
+    );
+}
+
+static BRANCHES: &str = r"
+fn clamp(x) -> (y) where {
+    assert y < 3;
+} = {
+    if x < 3 {
+        return (x)
+    } else {
+        return (2)
+    }
+}
+
+fn nonzero(x) -> (y) where {
+    assert y != 0;
+} = {
+    if x == 0 {
+        return (1)
+    } else {
+        return (x)
+    }
+}
+
+fn small(x) -> (y) where {
+    assert y < 2;
+} = {
+    match x {
+        0 => {
+            return (x)
+        },
+        1 => {
+            return (x)
+        },
+        _ => {
+            assert 2 <= x;
+            return (0)
+        },
+    }
+}
+";
+
+#[test]
+fn branch_assumptions() {
+    // every branch knows which condition led to it
+    desugar::check(&MultiFile::from_code(BRANCHES)).unwrap();
+
+    let wrong = BRANCHES.replace("assert y < 2;", "assert y < 1;");
+    assert_eq!(
+        check_err(&wrong),
+        "While checking the value against the type, The assertion is not always true"
+    );
+    let wrong = BRANCHES.replace("assert 2 <= x;", "assert 3 <= x;");
+    assert_eq!(check_err(&wrong), "The assertion is not always true");
+}
+
+#[test]
+fn match_patterns() {
+    let arms = |first, second| {
+        format!(
+            "fn m(x) -> (y) = {{\n    match x {{\n        {first} => {{\n            return (0)\n        }},\n        {second} => {{\n            return (1)\n        }},\n    }}\n}}\n"
+        )
+    };
+    desugar::check(&MultiFile::from_code(&arms("0", "_"))).unwrap();
+    desugar::check(&MultiFile::from_code(&arms("1", "_"))).unwrap();
+    let err = "Only the last match arm can be `_`, and it has to be there";
+    assert_eq!(check_err(&arms("0", "1")), err);
+    assert_eq!(check_err(&arms("_", "0")), err);
+}
+
+static SPARSE: &str = r"
+fn kind(c) -> (k) where {
+    assert k < 3;
+} = {
+    match c {
+        '\n' => {
+            return (0)
+        },
+        0x10 => {
+            return (1)
+        },
+        _ => {
+            assert c != 10;
+            return (2)
+        },
+    }
+}
+";
+
+#[test]
+fn sparse_match() {
+    // arms that are not 0, 1, 2, ... compare the value with their literal in turn
+    desugar::check(&MultiFile::from_code(SPARSE)).unwrap();
+    for (c, k) in [(10, 0), (0x10, 1), (0, 2), (1, 2), (11, 2)] {
+        let source = MultiFile::from_code(SPARSE);
+        let res = desugar::run(source, "kind", vec![c], vec![], AssertMode::Ghost);
+        assert_eq!(res.unwrap(), [k], "kind({c})");
+    }
+    let wrong = SPARSE.replace("return (1)", "return (3)");
+    assert_eq!(
+        check_err(&wrong),
+        "While checking the value against the type, The assertion is not always true"
+    );
+}
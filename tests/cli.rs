@@ -0,0 +1,17 @@
+use std::{env, fs, process::Command};
+
+/// The binaries are built with `panic = "abort"`, so errors must be returned instead of unwinding.
+#[test]
+fn report_and_exit() {
+    let path = env::temp_dir().join("structural_types_cli_error.lang");
+    fs::write(&path, "fn div(x, y) -> (z) = {\n    return (x / y)\n}\n").unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args([path.to_str().unwrap(), "div", "1", "1"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert_eq!(out.status.code(), Some(1), "{stderr}");
+    assert!(stderr.contains("The divisor might be zero"), "{stderr}");
+    assert!(stderr.contains("return (x / y)"), "{stderr}");
+}
@@ -0,0 +1,222 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+static ID: &str = r"
+fn id(x) -> (y) where {
+    assert y == x;
+} = {
+    return (x)
+}
+";
+
+static WRONG: &str = r"
+fn wrong(x) -> (y) where {
+    assert y < 1;
+} = {
+    return (x)
+}
+";
+
+#[test]
+fn type_error() {
+    let err = desugar::check(&MultiFile::from_code(WRONG)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "While checking the value against the type, The assertion is not always true"
+    );
+    // the checker can still be used after an error
+    desugar::check(&MultiFile::from_code(ID)).unwrap();
+}
+
+#[test]
+fn division() {
+    let code = "fn div(x, y) -> (z) = {\n    return (x / y)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(err.to_string(), "The divisor might be zero");
+    let code = "fn div(x, y) -> (z) = {\n    return (x /$ y)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(err.to_string(), "The divisor might be zero");
+}
+
+#[test]
+fn let_without_call() {
+    let code = "fn pair(a) -> (y) = {\n    let (x) = (a);\n    return (x)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(err.to_string(), "A `let` has to call a function");
+    let source = MultiFile::from_code(code);
+    let err = desugar::run(source, "pair", vec![1], vec![], AssertMode::Checked);
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "A `let` has to call a function"
+    );
+    assert!(desugar::compile(MultiFile::from_code(code)).is_err());
+}
+
+#[test]
+fn all_functions() {
+    let code = format!("{WRONG}{ID}fn scope() -> (y) = {{\n    return (z)\n}}\n");
+    let err = desugar::check(&MultiFile::from_code(&code)).unwrap_err();
+    assert_eq!(err.to_string(), "Found type errors in 2 functions");
+    let related: Vec<_> = err.related().unwrap().map(|e| e.to_string()).collect();
+    assert_eq!(
+        related,
+        [
+            "While checking the value against the type, The assertion is not always true",
+            "Can not find variable",
+        ]
+    );
+}
+
+#[test]
+fn syntax_error() {
+    let err = desugar::check(&MultiFile::from_code("fn (x)")).unwrap_err();
+    assert_eq!(err.to_string(), "Unexpected token: ParenL");
+}
+
+#[test]
+fn run() {
+    let res = desugar::run(
+        MultiFile::from_code(ID),
+        "id",
+        vec![42],
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap();
+    assert_eq!(res, [42]);
+    let err = desugar::run(
+        MultiFile::from_code(ID),
+        "missing",
+        vec![],
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "There is no function named `missing`");
+    let err = desugar::run(
+        MultiFile::from_code(ID),
+        "id",
+        vec![],
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The function takes 1 arguments, but 0 were given"
+    );
+}
+
+static SMALL: &str = r"
+fn small(x) -> (y) = {
+    assert x < 10;
+    return (x)
+}
+";
+
+#[test]
+fn false_assert() {
+    let err = desugar::check(&MultiFile::from_code(SMALL)).unwrap_err();
+    assert_eq!(err.to_string(), "The assertion is not always true");
+
+    let res = desugar::run(
+        MultiFile::from_code(SMALL),
+        "small",
+        vec![3],
+        vec![],
+        AssertMode::Checked,
+    );
+    assert_eq!(res.unwrap(), [3]);
+    let err = desugar::run(
+        MultiFile::from_code(SMALL),
+        "small",
+        vec![30],
+        vec![],
+        AssertMode::Checked,
+    );
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "The assertion failed at runtime"
+    );
+    // asserts are ghost code in checked programs
+    let res = desugar::run(
+        MultiFile::from_code(SMALL),
+        "small",
+        vec![30],
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [30]);
+}
+
+#[test]
+fn num_args() {
+    let code = format!("{ID}fn call() -> (y) = {{\n    let (a) = id(1, 2);\n    return (a)\n}}\n");
+    let err = desugar::check(&MultiFile::from_code(&code)).unwrap_err();
+    assert_eq!(err.to_string(), "Expected 1 values, but 2 were given");
+    let err = desugar::run(
+        MultiFile::from_code(&code),
+        "call",
+        vec![],
+        vec![],
+        AssertMode::Checked,
+    );
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "Expected 1 values, but 2 were given"
+    );
+}
+
+#[test]
+fn division_at_runtime() {
+    let code = "fn div(x, y) -> (z) = {\n    return (x / y)\n}\n";
+    let err = desugar::run(
+        MultiFile::from_code(code),
+        "div",
+        vec![1, 0],
+        vec![],
+        AssertMode::Checked,
+    );
+    assert_eq!(err.unwrap_err().to_string(), "Division by zero at runtime");
+}
+
+static BROKEN: &str = r"
+fn a(x) -> (y) = {
+    let = x;
+    return (x)
+}
+fn b(x) -> (y) = {
+    let (z) = ;
+    return (x)
+}
+";
+
+#[test]
+fn syntax_errors() {
+    // parsing continues after the first error
+    let err = desugar::check(&MultiFile::from_code(BROKEN)).unwrap_err();
+    assert_eq!(err.to_string(), "Found 2 syntax errors");
+    let related: Vec<_> = err.related().unwrap().map(|e| e.to_string()).collect();
+    assert_eq!(
+        related,
+        ["Unexpected token: Assign", "Unexpected token: Semi"]
+    );
+}
+
+#[test]
+fn source_order() {
+    let names = ["e", "a", "d", "b", "c"];
+    let code: String = names.iter().map(|n| WRONG.replace("wrong", n)).collect();
+    let broken = format!("{code}fn scope() -> (y) = {{\n    return (z)\n}}\n{ID}");
+    for _ in 0..10 {
+        // every function is checked, errors are returned instead of unwinding
+        let res = std::panic::catch_unwind(|| desugar::check(&MultiFile::from_code(&broken)));
+        let err = res.expect("the check should not panic").unwrap_err();
+        assert_eq!(err.to_string(), "Found type errors in 6 functions");
+        let labels: Vec<_> = err
+            .related()
+            .unwrap()
+            .map(|e| e.labels().unwrap().next().unwrap().offset())
+            .collect();
+        assert!(labels.is_sorted(), "{labels:?}");
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs;
+
+use structural_types::error::MultiFile;
+
+fn format(code: &str) -> String {
+    MultiFile::from_code(code).format().unwrap()
+}
+
+#[test]
+fn literals() {
+    let code = "const A = 0xFF + 'a' + 0b101 - -1 + ~A + (0 - A) + (A ^ -1);\n";
+    assert_eq!(format(code), code);
+    let code = "data BYTES = ['a', 0x10, 3];\n";
+    assert_eq!(format(code), code);
+    let code = "fn f(x) -> (y) = {\n    match x {\n        0x10 => {\n            return (1)\n        },\n        _ => {\n            return (x)\n        },\n    }\n}\n";
+    assert_eq!(format(code), code);
+}
+
+#[test]
+fn canonical() {
+    let code = "fn  f(x)->(y)=\n{ if (x<1) { return ((x+1)*2) }; return (x) }";
+    let expected = "fn f(x) -> (y) = {\n    if x < 1 {\n        return ((x + 1) * 2)\n    };\n    return (x)\n}\n";
+    assert_eq!(format(code), expected);
+}
+
+#[test]
+fn comments() {
+    let code = r"// leading comment
+fn f(x) -> (y) = {
+    // inside the block
+    let (z) = f(x); // behind a statement
+
+    // after a gap
+    return (z)
+    // before the closing brace
+}
+
+// at the end
+";
+    assert_eq!(format(code), code);
+}
+
+#[test]
+fn idempotent() {
+    for entry in fs::read_dir("lang").unwrap() {
+        let path = entry.unwrap().path();
+        let code = fs::read_to_string(&path).unwrap();
+        let once = format(&code);
+        assert_eq!(once, code, "{} is not formatted", path.display());
+        assert_eq!(format(&once), once);
+    }
+}
+
+#[test]
+fn quantifier() {
+    for path in [
+        "lang/sum_vec3.lang",
+        "lang/day1.lang",
+        "examples/array.lang",
+    ] {
+        let source = MultiFile::new(path).unwrap();
+        source.format().unwrap();
+    }
+    // both forms parse to the same constraint
+    let old = "type vec3s(from) where {\n    [p] = vec3 if p - from < 3;\n}\n";
+    let new = "type vec3s(from) where {\n    vec3 for (p) if p - from < 3;\n}\n";
+    assert_eq!(format(old), new);
+    assert_eq!(format(new), new);
+}
+
+#[test]
+fn access() {
+    let typ = |constraint: &str| {
+        format!("fn f(p) where {{\n    {constraint};\n}} -> () = {{\n    return ()\n}}\n")
+    };
+    for single in [
+        "x = move @byte(p)",
+        "x = inspect @byte(p)",
+        "inspect @byte(p)",
+    ] {
+        assert_eq!(format(&typ(single)), typ(single));
+    }
+    // the access can also be written before the name
+    assert_eq!(format(&typ("move x = @byte(p)")), typ("x = move @byte(p)"));
+
+    // but the resource can only be annotated once
+    let double = [
+        ("move x = inspect @byte(p)", "InspectKeyword"),
+        ("inspect x = move @byte(p)", "MoveKeyword"),
+    ];
+    for (code, token) in double {
+        let err = MultiFile::from_code(&typ(code)).format().unwrap_err();
+        assert_eq!(err.to_string(), format!("Unexpected token: {token}"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{env, fs, path::PathBuf};
+
+use miette::SourceCode;
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+/// Write the files to a fresh directory and return the path of the first one.
+fn write(dir: &str, files: &[(&str, &str)]) -> String {
+    let dir: PathBuf = env::temp_dir().join(dir);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, code) in files {
+        fs::write(dir.join(name), code).unwrap();
+    }
+    dir.join(files[0].0).to_string_lossy().into_owned()
+}
+
+static LIB: &str = "fn inc(x) -> (y) = {\n    return (x + 1)\n}\n";
+static OTHER: &str =
+    "import \"lib.lang\";\n\nfn twice(x) -> (y) = {\n    let (a) = inc(x);\n    return inc(a)\n}\n";
+static MAIN: &str = "import \"lib.lang\";\nimport \"other.lang\";\n\nfn main(x) -> (y) = {\n    let (a) = inc(x);\n    return twice(a)\n}\n";
+
+#[test]
+fn resolve() {
+    let path = write(
+        "structural_types_resolve",
+        &[
+            ("main.lang", MAIN),
+            ("lib.lang", LIB),
+            ("other.lang", OTHER),
+        ],
+    );
+    let source = MultiFile::new(&path).unwrap();
+    // every file is loaded once, even when it is imported twice
+    assert_eq!(source.files.len(), 3);
+    desugar::check(&source).unwrap();
+    let res = desugar::run(source, "main", vec![1], vec![], AssertMode::Ghost);
+    assert_eq!(res.unwrap(), [4]);
+}
+
+#[test]
+fn missing() {
+    let path = write(
+        "structural_types_missing",
+        &[("main.lang", "import \"missing.lang\";\n")],
+    );
+    let err = MultiFile::new(&path).err().unwrap();
+    assert_eq!(err.to_string(), "Can not read imported file");
+}
+
+#[test]
+fn duplicate() {
+    let again = "import \"lib.lang\";\n\nfn inc(x) -> (y) = {\n    return (x)\n}\n";
+    let path = write(
+        "structural_types_duplicate",
+        &[("main.lang", again), ("lib.lang", LIB)],
+    );
+    let source = MultiFile::new(&path).unwrap();
+    let err = desugar::check(&source).unwrap_err();
+    assert_eq!(err.to_string(), "The name `inc` is already defined");
+}
+
+#[test]
+fn error_in_import() {
+    let wrong = "fn inc(x) -> (y) = {\n    return (z)\n}\n";
+    let path = write(
+        "structural_types_error_in_import",
+        &[("main.lang", "import \"lib.lang\";\n"), ("lib.lang", wrong)],
+    );
+    let source = MultiFile::new(&path).unwrap();
+    let err = desugar::check(&source).unwrap_err();
+    assert_eq!(err.to_string(), "Can not find variable");
+
+    // the label is shown in the imported file
+    let label = err.labels().unwrap().next().unwrap();
+    let contents = source.read_span(label.inner(), 0, 0).unwrap();
+    assert!(contents.name().unwrap().ends_with("lib.lang"));
+    assert_eq!(contents.line(), 1);
+    assert_eq!(contents.data(), b"z");
+}
@@ -0,0 +1,164 @@
+use structural_types::parse::lexer::{Lexer, Token};
+
+fn lex(input: &str) -> Vec<Token> {
+    Lexer::new(input, 0).map(|res| res.unwrap().1).collect()
+}
+
+fn lex_num(input: &str) -> u64 {
+    let [Token::Num(num)] = *lex(input) else {
+        panic!("expected a single number for {input}")
+    };
+    num
+}
+
+#[test]
+fn decimal() {
+    assert_eq!(lex_num("0"), 0);
+    assert_eq!(lex_num("4096"), 4096);
+    assert_eq!(lex_num("4294967295"), 4294967295);
+}
+
+#[test]
+fn hex() {
+    assert_eq!(lex_num("0xFF"), 255);
+    assert_eq!(lex_num("0x1f"), 31);
+    assert_eq!(lex_num("0xFFFFFFFF"), 4294967295);
+    // the width is checked where the literal is used
+    assert_eq!(lex_num("0x100000000"), 1 << 32);
+    assert_eq!(lex_num("0xFFFFFFFFFFFFFFFF"), u64::MAX);
+}
+
+#[test]
+fn binary() {
+    assert_eq!(lex_num("0b1010"), 10);
+    assert_eq!(lex_num("0b0"), 0);
+}
+
+#[test]
+fn char_literal() {
+    assert_eq!(lex_num("'a'"), 97);
+    assert_eq!(lex_num("'0'"), 48);
+    assert_eq!(lex_num("' '"), 32);
+    assert_eq!(lex_num("'~'"), 126);
+}
+
+#[test]
+fn escape() {
+    assert_eq!(lex_num(r"'\n'"), 10);
+    assert_eq!(lex_num(r"'\t'"), 9);
+    assert_eq!(lex_num(r"'\r'"), 13);
+    assert_eq!(lex_num(r"'\0'"), 0);
+    assert_eq!(lex_num(r"'\\'"), 92);
+    assert_eq!(lex_num(r"'\''"), 39);
+    assert_eq!(lex_num(r#"'\"'"#), 34);
+}
+
+#[test]
+fn string() {
+    let tokens = lex(r#"import "lib/array.lang";"#);
+    assert_eq!(tokens[1], Token::Str("lib/array.lang".to_owned()));
+    assert_eq!(lex(r#""a\n\"b""#), [Token::Str("a\n\"b".to_owned())]);
+}
+
+#[test]
+fn in_expression() {
+    let tokens = lex("x + 0xFF");
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[2], Token::Num(255));
+}
+
+#[test]
+fn bitwise() {
+    let tokens = lex("a & b | c ^ ~d && e || &f");
+    let ops: Vec<_> = tokens
+        .into_iter()
+        .filter(|t| !matches!(t, Token::Var(_)))
+        .collect();
+    assert_eq!(
+        ops,
+        [
+            Token::BitAnd,
+            Token::BitOr,
+            Token::BitXor,
+            Token::BitNot,
+            Token::And,
+            Token::Or,
+            Token::BitAnd,
+        ]
+    );
+}
+
+#[test]
+fn comment() {
+    let tokens = lex("x // 0xFF\n1");
+    assert_eq!(tokens, [Token::Var("x".to_owned()), Token::Num(1)]);
+}
+
+#[test]
+fn match_arm() {
+    let tokens = lex("_ => {");
+    assert_eq!(tokens[0], Token::Var("_".to_owned()));
+    assert_eq!(tokens[1], Token::FatArrow);
+    assert_eq!(lex("matches"), [Token::Var("matches".to_owned())]);
+}
+
+#[test]
+fn type_args() {
+    let tokens = lex("array<@byte>");
+    assert_eq!(tokens[1], Token::Less);
+    assert_eq!(tokens[3], Token::Greater);
+    assert_eq!(lex("a >> b")[1], Token::Shr);
+}
+
+#[test]
+fn extend() {
+    assert_eq!(lex("x as u64")[1], Token::AsKeyword);
+    assert_eq!(lex("x as$ u64")[1], Token::SAsKeyword);
+    assert_eq!(lex("ask"), [Token::Var("ask".to_owned())]);
+}
+
+#[test]
+fn const_def() {
+    let tokens = lex(r"const NEWLINE = '\n';");
+    assert_eq!(
+        tokens,
+        [
+            Token::ConstKeyword,
+            Token::Var("NEWLINE".to_owned()),
+            Token::Assign,
+            Token::Num(10),
+            Token::Semi,
+        ]
+    );
+    assert_eq!(lex("constant"), [Token::Var("constant".to_owned())]);
+}
+
+#[test]
+fn data_def() {
+    let tokens = lex(r#"data MSG = "hi\n";"#);
+    assert_eq!(
+        tokens,
+        [
+            Token::DataKeyword,
+            Token::Var("MSG".to_owned()),
+            Token::Assign,
+            Token::Str("hi\n".to_owned()),
+            Token::Semi,
+        ]
+    );
+}
+
+#[test]
+fn invalid() {
+    for input in [
+        "0x10000000000000000",
+        "99999999999999999999",
+        "'\\x'",
+        "''",
+        "'ab'",
+        r#""\x""#,
+    ] {
+        let lexed: Result<Vec<_>, _> = Lexer::new(input, 0).collect();
+        assert!(lexed.is_err(), "{input} should not lex");
+    }
+}
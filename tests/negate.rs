@@ -0,0 +1,72 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+fn run(code: &str, name: &str, args: Vec<i64>) -> Vec<i64> {
+    desugar::run(
+        MultiFile::from_code(code),
+        name,
+        args,
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap()
+}
+
+static NEGATE: &str = r"
+fn one() -> (y) where {
+    assert y == 0xFFFFFFFF;
+} = {
+    return (-1)
+}
+
+fn neg(x) -> (y) where {
+    assert x + y == 0;
+} = {
+    return (-x)
+}
+
+fn min() -> (y) where {
+    assert y == 0x80000000;
+} = {
+    return (- 2147483648)
+}
+
+fn twice() -> (y) where {
+    assert y == 0x80000000;
+} = {
+    let (x) = min();
+    return (-x)
+}
+";
+
+#[test]
+fn checker() {
+    desugar::check(&MultiFile::from_code(NEGATE)).unwrap();
+
+    let code = "fn wrong(x) -> (y) where {\n    assert y == x;\n} = {\n    return (-x)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "While checking the value against the type, The assertion is not always true"
+    );
+}
+
+#[test]
+fn interpreter() {
+    assert_eq!(run(NEGATE, "one", vec![]), [-1]);
+    assert_eq!(run(NEGATE, "neg", vec![5]), [-5]);
+    assert_eq!(run(NEGATE, "neg", vec![-5]), [5]);
+    assert_eq!(run(NEGATE, "neg", vec![0]), [0]);
+    assert_eq!(run(NEGATE, "min", vec![]), [i32::MIN as i64]);
+    // negating the smallest value wraps around to itself
+    assert_eq!(run(NEGATE, "twice", vec![]), [i32::MIN as i64]);
+}
+
+#[test]
+fn too_small() {
+    let code = "fn min() -> (y) = {\n    return (- 2147483649)\n}\n";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The literal -2147483649 does not fit in 32 bits"
+    );
+}
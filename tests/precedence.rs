@@ -0,0 +1,77 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+fn func(expr: &str) -> String {
+    format!("fn f(a, b, c, d) -> (y) = {{\n    return ({expr})\n}}\n")
+}
+
+/// The formatter prints the parsed tree, so equal trees format the same.
+fn tree(expr: &str) -> String {
+    MultiFile::from_code(&func(expr)).format().unwrap()
+}
+
+static SAME: &[(&str, &str)] = &[
+    ("a + b * c", "a + (b * c)"),
+    ("a * b + c", "(a * b) + c"),
+    ("a - b - c", "(a - b) - c"),
+    ("a / b % c", "(a / b) % c"),
+    ("a << b + 1", "a << (b + 1)"),
+    ("a >> b >>$ c", "(a >> b) >>$ c"),
+    ("a & b ^ c | d", "((a & b) ^ c) | d"),
+    ("a | b & c", "a | (b & c)"),
+    ("-a * ~b", "(-a) * (~b)"),
+    ("a as u64 + b as u64", "(a as u64) + (b as u64)"),
+    ("a + 1 < b << 2", "(a + 1) < (b << 2)"),
+    ("a & b == c", "(a & b) == c"),
+    (
+        "a < b && b < c || a == d",
+        "((a < b) && (b < c)) || (a == d)",
+    ),
+    ("a || b && c", "a || (b && c)"),
+    ("a && b && c", "(a && b) && c"),
+    (
+        "(d << 24) + (c << 16) + (b << 8) + a",
+        "(((d << 24) + (c << 16)) + (b << 8)) + a",
+    ),
+];
+
+#[test]
+fn parenthesized() {
+    for (chained, nested) in SAME {
+        assert_eq!(tree(chained), tree(nested), "{chained}");
+    }
+}
+
+#[test]
+fn associativity() {
+    assert_ne!(tree("a - b - c"), tree("a - (b - c)"));
+    assert_ne!(tree("a << b << c"), tree("a << (b << c)"));
+    assert_ne!(tree("a + b * c"), tree("(a + b) * c"));
+}
+
+#[test]
+fn evaluation() {
+    let bytes = [0x12, 0x34, 0x56, 0x78];
+    let code = func(SAME.last().unwrap().0);
+    let res = desugar::run(
+        MultiFile::from_code(&code),
+        "f",
+        bytes.into(),
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [0x78563412]);
+    let code = func("a - b - c");
+    let res = desugar::run(
+        MultiFile::from_code(&code),
+        "f",
+        vec![10, 3, 2, 0],
+        vec![],
+        AssertMode::Ghost,
+    );
+    assert_eq!(res.unwrap(), [5]);
+}
+
+#[test]
+fn comparisons_do_not_chain() {
+    assert!(MultiFile::from_code(&func("a < b < c")).format().is_err());
+}
@@ -0,0 +1,88 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+fn run(code: &str, name: &str, args: Vec<i64>) -> Vec<i64> {
+    desugar::run(
+        MultiFile::from_code(code),
+        name,
+        args,
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap()
+}
+
+static OPS: &str = r"
+fn sshr(a, b) -> (x) = {
+    return (a >>$ b)
+}
+
+fn sdiv(a, b) -> (x) = {
+    return (a /$ b)
+}
+
+fn srem(a, b) -> (x) = {
+    return (a %$ b)
+}
+
+fn less(a, b) -> (x) = {
+    return (a <$ b)
+}
+";
+
+fn sized(size: &str) -> String {
+    let code = OPS.replace("(a, b)", &format!("(a: {size}, b: {size})"));
+    code.replace("(x)", &format!("(x: {size})"))
+}
+
+#[test]
+fn shift_sign() {
+    let code = sized("u32");
+    assert_eq!(run(&code, "sshr", vec![-8, 1]), [-4]);
+    // shifting out all bits leaves the sign
+    assert_eq!(run(&code, "sshr", vec![-8, 32]), [-1]);
+    assert_eq!(run(&code, "sshr", vec![-8, 100]), [-1]);
+    assert_eq!(run(&code, "sshr", vec![8, 32]), [0]);
+
+    let code = sized("u8");
+    assert_eq!(run(&code, "sshr", vec![0x80, 7]), [-1]);
+    assert_eq!(run(&code, "sshr", vec![0x80, 200]), [-1]);
+    assert_eq!(run(&code, "sshr", vec![0x40, 200]), [0]);
+
+    let code = sized("u64");
+    assert_eq!(run(&code, "sshr", vec![i64::MIN, 64]), [-1]);
+}
+
+#[test]
+fn division_overflow() {
+    for (size, min) in [("u8", -128), ("u32", i32::MIN as i64), ("u64", i64::MIN)] {
+        let code = sized(size);
+        // the quotient does not fit, so it wraps around
+        assert_eq!(run(&code, "sdiv", vec![min, -1]), [min]);
+        assert_eq!(run(&code, "srem", vec![min, -1]), [0]);
+        assert_eq!(run(&code, "sdiv", vec![-7, 2]), [-3]);
+        assert_eq!(run(&code, "srem", vec![-7, 2]), [-1]);
+        assert_eq!(run(&code, "less", vec![min, 0]), [1]);
+    }
+}
+
+#[test]
+fn checked_division() {
+    let code = r"
+fn sdiv(a: u8, b: u8) where {
+    assert b != 0;
+} -> (x: u8) = {
+    return (a /$ b)
+}
+";
+    let err = desugar::check(&MultiFile::from_code(code)).unwrap_err();
+    assert_eq!(err.to_string(), "The signed division might overflow");
+    let code = r"
+fn sdiv(a: u8, b: u8) where {
+    assert b != 0;
+    assert b != 255;
+} -> (x: u8) = {
+    return (a /$ b)
+}
+";
+    desugar::check(&MultiFile::from_code(code)).unwrap();
+}
@@ -0,0 +1,364 @@
+use std::iter::zip;
+
+use structural_types::{
+    desugar,
+    error::MultiFile,
+    refinement::eval::{AssertMode, Int},
+};
+use wasmi::{core::ValType, Config, Engine, Instance, Linker, Module, Store, Val};
+
+/// Check the program and validate the encoded module.
+fn validate(source: MultiFile) {
+    desugar::check(&source).unwrap();
+    let module = desugar::compile(source).unwrap();
+    if let Err(err) = wasmparser::validate(&module.encode()) {
+        panic!("{err}\n{module}");
+    }
+}
+
+/// An instance of the compiled program, to compare it with the interpreter.
+struct Wasm {
+    source: MultiFile,
+    store: Store<()>,
+    instance: Instance,
+}
+
+impl Wasm {
+    fn new(source: MultiFile) -> Self {
+        validate(source.clone());
+        let bytes = desugar::compile(source.clone()).unwrap().encode();
+        let mut config = Config::default();
+        config.wasm_tail_call(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes).unwrap();
+        let mut store = Store::new(&engine, ());
+        let linker = Linker::new(&engine);
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let instance = instance.start(&mut store).unwrap();
+        Self {
+            source,
+            store,
+            instance,
+        }
+    }
+
+    /// Call the exported function, the results are unsigned.
+    fn call(&mut self, name: &str, args: &[i64]) -> Vec<u64> {
+        let func = self.instance.get_func(&self.store, name).unwrap();
+        let typ = func.ty(&self.store);
+        let args: Vec<_> = zip(args, typ.params())
+            .map(|(arg, typ)| match typ {
+                ValType::I64 => Val::I64(*arg),
+                _ => Val::I32(*arg as i32),
+            })
+            .collect();
+        let mut res: Vec<_> = typ.results().iter().map(|typ| Val::default(*typ)).collect();
+        func.call(&mut self.store, &args, &mut res).unwrap();
+        res.iter()
+            .map(|val| match val {
+                Val::I64(val) => *val as u64,
+                val => val.i32().unwrap() as u32 as u64,
+            })
+            .collect()
+    }
+
+    /// Check that wasm gives the same results as the interpreter.
+    /// The arguments have to fit in their parameters, the results are `size` bits.
+    fn agree(&mut self, name: &str, args: &[i64], size: u32) {
+        let source = self.source.clone();
+        let res = desugar::run(source, name, args.to_vec(), vec![], AssertMode::Ghost).unwrap();
+        let expected: Vec<_> = res.iter().map(|val| Int::new(*val, size).val).collect();
+        assert_eq!(self.call(name, args), expected, "{name}{args:?}");
+    }
+}
+
+static OPS: &str = r"
+fn ops(a, b) -> (x, y, z) = {
+    return ((a + b) - (a * b), (a / 3) + (a % 3) + (a /$ 3) + (a %$ 3), (a & b) | (a ^ ~b))
+}
+
+fn compare(a, b) -> (x) = {
+    return ((a < b) + (a <= b) + (a <$ b) + (a <=$ b) + (a == b) + (a != b) + ((a < 1) && (b < 1)) + ((a < 1) || (b < 1)))
+}
+
+fn safe(a, b) -> (x, y) = {
+    return (a *? b, a +? b)
+}
+
+fn shift(a, b) -> (x, y, z) = {
+    return (a << b, a >> b, a >>$ b)
+}
+";
+
+#[test]
+fn operators() {
+    for size in ["u8", "u16", "u32", "u64"] {
+        let code = OPS.replace("(a, b)", &format!("(a: {size}, b: {size})"));
+        let code = code.replace("(x, y, z)", &format!("(x: {size}, y: {size}, z: {size})"));
+        let bits = size[1..].parse().unwrap();
+
+        let mut wasm = Wasm::new(MultiFile::from_code(&code));
+        let max = Int::new(-1, bits).val as i64;
+        let half = max / 2;
+        let vals = [0, 1, 2, 3, 7, 100, half, half + 1, max - 1, max];
+        // shift counts that are not smaller than the width saturate
+        let counts = [0, 1, 7, 8, 15, 16, 31, 32, 63, 64, 200];
+        for a in vals {
+            for b in vals {
+                let args = [a, b].map(|val| Int::new(val, bits).val as i64);
+                wasm.agree("ops", &args, bits);
+                // comparisons give 32 bit values at every width
+                wasm.agree("compare", &args, 32);
+                wasm.agree("safe", &args, 32);
+            }
+            for b in counts {
+                let args = [a, b].map(|val| Int::new(val, bits).val as i64);
+                wasm.agree("shift", &args, bits);
+            }
+        }
+    }
+}
+
+static CONTROL: &str = r"
+data DIGITS = [48, 49, 50];
+
+fn branches(x: u64, b: u8) -> (y: u64) = {
+    #debug;
+    assert 0 <= x;
+    if x == 0 {
+        return (1)
+    };
+    match x {
+        0 => {
+            return (3)
+        },
+        _ => {
+            return (x + (b as$ u64) + (b as u64))
+        },
+    }
+}
+
+fn choose(x) -> (y) = {
+    if x == 0 {
+        return (1)
+    } else if x == 1 {
+        return (2)
+    } else {
+        return (3)
+    }
+}
+
+fn count(n) -> (i) = {
+    loop up(i) = {
+        if i == n {
+            return (i)
+        };
+        return up(i + 1)
+    };
+    return up(0)
+}
+
+fn digit(d) where {
+    assert d < 3;
+} -> (c) = {
+    return (DIGITS[d])
+}
+
+fn apply(f, x) where {
+    fn f(y) -> (z);
+} -> (z) = {
+    let (z) = f(x);
+    return (z)
+}
+
+fn kind(c) -> (k) = {
+    match c {
+        '\n' => {
+            return (0)
+        },
+        0x10 => {
+            return (1)
+        },
+        _ => {
+            return (2)
+        },
+    }
+}
+
+fn indirect(x) -> (z) = {
+    return apply(&count, x)
+}
+";
+
+#[test]
+fn control_flow() {
+    let mut wasm = Wasm::new(MultiFile::from_code(CONTROL));
+    for x in [0, 1, 2, 5, u64::MAX as i64] {
+        for b in [0, 1, 0x7F, 0x80, 0xFF] {
+            wasm.agree("branches", &[x, b], 64);
+        }
+    }
+    for x in 0..5 {
+        wasm.agree("choose", &[x], 32);
+        wasm.agree("count", &[x], 32);
+        wasm.agree("indirect", &[x], 32);
+    }
+    for c in [0, 1, 10, 11, 0x10, 0x110] {
+        wasm.agree("kind", &[c], 32);
+    }
+    for d in 0..3 {
+        wasm.agree("digit", &[d], 32);
+    }
+}
+
+#[test]
+fn lang_files() {
+    for path in ["array", "heap", "linked", "runnable"] {
+        let path = format!("lang/{path}.lang");
+        validate(MultiFile::new(&path).unwrap());
+    }
+}
+
+static TABLE: &str = r"
+fn same(x) -> (y) = {
+    return (x)
+}
+
+fn apply(f, x) where {
+    fn f(a) -> (b);
+} -> (y) = {
+    let (y) = f(x);
+    return (y)
+}
+
+fn call(x) -> (y) = {
+    return apply(&same, x)
+}
+";
+
+#[test]
+fn table() {
+    validate(MultiFile::from_code(TABLE));
+    let wat = desugar::compile(MultiFile::from_code(TABLE))
+        .unwrap()
+        .to_string();
+    // pointers are indices in the table, which has all functions in source order
+    assert!(
+        wat.contains("(elem (i32.const 0) $same $apply $call)"),
+        "{wat}"
+    );
+    assert!(
+        wat.contains("call_indirect (param i32) (result i32)"),
+        "{wat}"
+    );
+    Wasm::new(MultiFile::from_code(TABLE)).agree("call", &[5], 32);
+}
+
+static SNAPSHOT: &str = r#"
+data DIGITS = ['0', '1', 0, '"'];
+
+fn count(n) -> (i) = {
+    loop up(i) = {
+        if i == n {
+            return (i)
+        };
+        return up(i + 1)
+    };
+    return up(0)
+}
+
+fn digit(d) where {
+    assert d < 2;
+} -> (c) = {
+    return (DIGITS[d])
+}
+"#;
+
+#[test]
+fn wat_snapshot() {
+    let mut wasm = Wasm::new(MultiFile::from_code(SNAPSHOT));
+    wasm.agree("count", &[3], 32);
+    wasm.agree("digit", &[1], 32);
+    let wat = desugar::compile(MultiFile::from_code(SNAPSHOT))
+        .unwrap()
+        .to_string();
+    // loops become their own function, entered with a tail call
+    let expected = r#"(module
+  (memory (export "memory") 1)
+  (global $heap_end (export "heap_end") (mut i32) (i32.const 4))
+  (data (i32.const 0) "01\00\"")
+  (table 2 funcref)
+  (elem (i32.const 0) $count $digit)
+  (func $count (export "count") (param i32) (result i32)
+    i32.const 0
+    local.get 0
+    return_call $count.up
+  )
+  (func $digit (export "digit") (param i32) (result i32) (local i32)
+    i32.const 0
+    local.get 0
+    i32.add
+    i32.load8_u
+    local.set 1
+    local.get 1
+    return
+  )
+  (func $count.up (param i32 i32) (result i32)
+    loop $count.up
+      block $b0
+        block $b1
+          local.get 0
+          local.get 1
+          i32.eq
+          br_table $b1 $b0
+        end
+        local.get 0
+        i32.const 1
+        i32.add
+        local.set 0
+        br $count.up
+      end
+      local.get 0
+      return
+    end
+    unreachable
+  )
+)
+"#;
+    assert_eq!(wat, expected);
+}
+
+static SHADOW: &str = r"
+fn id(v) -> (w) = {
+    return (v)
+}
+
+fn shadow(x) -> (y) = {
+    loop a(i) = {
+        return (i + x)
+    };
+    let (x) = id(x + 10);
+    loop b(j) = {
+        return a(j)
+    };
+    return b(x)
+}
+";
+
+#[test]
+fn shadowed_capture() {
+    let source = MultiFile::from_code(SHADOW);
+    let res = desugar::run(source.clone(), "shadow", vec![1], vec![], AssertMode::Ghost);
+    assert_eq!(res.unwrap(), [12]);
+    let mut wasm = Wasm::new(source);
+    assert_eq!(wasm.call("shadow", &[1]), [12]);
+    // `b` does not see the first `x`, but it passes it on to `a`
+    let wat = desugar::compile(MultiFile::from_code(SHADOW))
+        .unwrap()
+        .to_string();
+    assert!(wat.contains("(func $shadow.b (param i32 i32 i32)"), "{wat}");
+    assert!(
+        wat.contains("local.get 2\n    return_call $shadow.a"),
+        "{wat}"
+    );
+}
@@ -0,0 +1,64 @@
+use structural_types::{desugar, error::MultiFile, refinement::eval::AssertMode};
+
+fn run(code: &str, name: &str, args: Vec<i64>) -> Vec<i64> {
+    desugar::check(&MultiFile::from_code(code)).unwrap();
+    desugar::run(
+        MultiFile::from_code(code),
+        name,
+        args,
+        vec![],
+        AssertMode::Checked,
+    )
+    .unwrap()
+}
+
+fn check_err(code: &str) -> String {
+    desugar::check(&MultiFile::from_code(code))
+        .unwrap_err()
+        .to_string()
+}
+
+static WIDEN: &str = r"
+fn unsigned(x: u64) -> (y: u64) = {
+    return (x + 0xFFFFFFFF)
+}
+
+fn minus(x: u64) -> (y: u64) = {
+    return (x + -1)
+}
+
+fn large(x: u64) -> (y: u64) = {
+    return (x ^ 0x100000000)
+}
+
+fn byte(x: u8) -> (y: u8) = {
+    return (x + 255)
+}
+";
+
+#[test]
+fn widening() {
+    assert_eq!(run(WIDEN, "unsigned", vec![1]), [1 << 32]);
+    assert_eq!(run(WIDEN, "minus", vec![1]), [0]);
+    assert_eq!(run(WIDEN, "large", vec![1]), [(1 << 32) + 1]);
+    assert_eq!(run(WIDEN, "byte", vec![2]), [1]);
+}
+
+#[test]
+fn literal_too_large() {
+    let code = "fn byte(x: u8) -> (y: u8) = {\n    return (x + 300)\n}\n";
+    assert_eq!(check_err(code), "The literal 300 does not fit in 8 bits");
+    let code = "fn byte(x: u8) -> (y: u8) = {\n    return (x + -129)\n}\n";
+    assert_eq!(check_err(code), "The literal -129 does not fit in 8 bits");
+    let code = "fn word(x) -> (y) = {\n    return (x + 0x100000000)\n}\n";
+    assert_eq!(
+        check_err(code),
+        "The literal 4294967296 does not fit in 32 bits"
+    );
+}
+
+#[test]
+fn mixed_widths() {
+    let code = "fn mixed(x: u8, y) -> (z) = {\n    return (x + y)\n}\n";
+    assert_eq!(check_err(code), "Can not combine values of 8 and 32 bits");
+}